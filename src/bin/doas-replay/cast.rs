@@ -0,0 +1,161 @@
+//!This module reads the asciicast v2 files doas records sessions to.
+//!The first line is a JSON header, with a "doas" object saying whose session it was,
+//!and every line after it is an `[seconds, code, data]` event.
+use serde_json::Value;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+///What the header says about a session.
+#[derive(Debug, PartialEq)]
+pub struct Header {
+    pub session: String,
+    pub user: String,
+    pub target: String,
+    pub command: String,
+    ///When the session started, in seconds since the epoch.
+    pub timestamp: i64,
+}
+
+///Something that happened in a session, `seconds` after it started.
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    pub time: f64,
+    pub kind: EventKind,
+    pub data: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EventKind {
+    Output,
+    Input,
+    Resize,
+    ///An event asciicast has, but doas doesn't record, such as a marker.
+    Other,
+}
+
+///The ways reading a session can fail.
+#[derive(Debug)]
+pub enum CastError {
+    Io(PathBuf, io::Error),
+    ///The line (starting at 1) isn't what a session file should have.
+    Invalid(PathBuf, usize),
+}
+
+impl std::fmt::Display for CastError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "couldn't read {}: {}", path.display(), e),
+            Self::Invalid(path, line) => write!(
+                f,
+                "{}:{}: isn't a session recorded in asciicast v2 format",
+                path.display(),
+                line
+            ),
+        }
+    }
+}
+
+///A session file being read.
+pub struct Cast {
+    path: PathBuf,
+    lines: io::Lines<BufReader<File>>,
+    line: usize,
+    pub header: Header,
+}
+
+impl Cast {
+    ///Opens the session at path, and reads its header.
+    pub fn open(path: &Path) -> Result<Self, CastError> {
+        let file = File::open(path).map_err(|e| CastError::Io(path.to_owned(), e))?;
+        let mut lines = BufReader::new(file).lines();
+        let header = match lines.next() {
+            Some(Ok(line)) => parse_header(&line),
+            Some(Err(e)) => return Err(CastError::Io(path.to_owned(), e)),
+            None => None,
+        };
+        let header = header.ok_or_else(|| CastError::Invalid(path.to_owned(), 1))?;
+        Ok(Self {
+            path: path.to_owned(),
+            lines,
+            line: 1,
+            header,
+        })
+    }
+}
+
+impl Iterator for Cast {
+    type Item = Result<Event, CastError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(CastError::Io(self.path.clone(), e))),
+        };
+        self.line += 1;
+        Some(parse_event(&line).ok_or_else(|| CastError::Invalid(self.path.clone(), self.line)))
+    }
+}
+
+fn parse_header(line: &str) -> Option<Header> {
+    let header: Value = serde_json::from_str(line).ok()?;
+    if header["version"] != 2 {
+        return None;
+    }
+    let doas = &header["doas"];
+    let field = |value: &Value| value.as_str().unwrap_or("?").to_owned();
+    Some(Header {
+        session: field(&doas["session"]),
+        user: field(&doas["user"]),
+        target: field(&doas["target"]),
+        command: field(doas.get("command").unwrap_or(&header["title"])),
+        timestamp: header["timestamp"].as_i64().unwrap_or(0),
+    })
+}
+
+fn parse_event(line: &str) -> Option<Event> {
+    let event: Value = serde_json::from_str(line).ok()?;
+    let kind = match event.get(1)?.as_str()? {
+        "o" => EventKind::Output,
+        "i" => EventKind::Input,
+        "r" => EventKind::Resize,
+        _ => EventKind::Other,
+    };
+    Some(Event {
+        time: event.get(0)?.as_f64()?,
+        kind,
+        data: event.get(2)?.as_str()?.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse() {
+        let header = r#"{"version":2,"width":80,"height":24,"timestamp":1600695902,"title":"sh",
+            "doas":{"session":"20200921-134502-4123","user":"alice","target":"root","command":"sh"}}"#;
+        assert_eq!(
+            parse_header(&header.replace('\n', "")),
+            Some(Header {
+                session: "20200921-134502-4123".to_owned(),
+                user: "alice".to_owned(),
+                target: "root".to_owned(),
+                command: "sh".to_owned(),
+                timestamp: 1600695902,
+            })
+        );
+        assert_eq!(parse_header(r#"{"version":1}"#), None);
+        assert_eq!(
+            parse_event(r##"[0.5, "o", "# \u001b[1mls\u001b[0m\r\n"]"##),
+            Some(Event {
+                time: 0.5,
+                kind: EventKind::Output,
+                data: "# \x1b[1mls\x1b[0m\r\n".to_owned(),
+            })
+        );
+        assert_eq!(parse_event(r#"[0.5, "o"]"#), None);
+    }
+}
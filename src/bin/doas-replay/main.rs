@@ -0,0 +1,221 @@
+//!This program lists and replays the sessions doas records for rules with the `record` option.
+//!Sessions are asciicast v2 files, so they can also be played with anything else that
+//!understands the format, but this needs nothing but a terminal.
+//!The cast module reads session files, and transcript turns their output into plain text.
+use chrono::{Local, NaiveDate, TimeZone};
+use structopt::StructOpt;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+mod cast;
+mod transcript;
+
+use cast::{Cast, CastError, EventKind, Header};
+use transcript::Transcript;
+
+#[derive(Debug, StructOpt)]
+struct Options {
+    ///Read sessions from this directory.
+    #[structopt(
+        parse(from_os_str),
+        short = "d",
+        long = "dir",
+        default_value = "/var/log/doas/sessions"
+    )]
+    dir: PathBuf,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    ///List the recorded sessions, oldest first.
+    List {
+        ///Only list sessions run by this user.
+        #[structopt(short = "u", long = "user")]
+        user: Option<String>,
+
+        ///Only list sessions started on or after this date, given as YYYY-MM-DD.
+        #[structopt(long = "since", parse(try_from_str = parse_date))]
+        since: Option<NaiveDate>,
+
+        ///Only list sessions started on or before this date, given as YYYY-MM-DD.
+        #[structopt(long = "until", parse(try_from_str = parse_date))]
+        until: Option<NaiveDate>,
+    },
+    ///Play a session back on the terminal, as it was recorded.
+    Play {
+        ///Play it this many times faster.
+        #[structopt(short = "s", long = "speed", default_value = "1", parse(try_from_str = parse_speed))]
+        speed: f64,
+
+        ///Never wait longer than this many seconds between output, however long the session was idle.
+        #[structopt(short = "m", long = "max-wait")]
+        max_wait: Option<f64>,
+
+        ///The ID of the session, or the path to its file.
+        session: String,
+    },
+    ///Print what a session output as plain text, without any of the escape sequences.
+    Dump {
+        ///The ID of the session, or the path to its file.
+        session: String,
+    },
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| format!("{}, expected YYYY-MM-DD", e))
+}
+
+fn parse_speed(speed: &str) -> Result<f64, String> {
+    match speed.parse() {
+        Ok(speed) if speed > 0.0 => Ok(speed),
+        _ => Err("expected a number above 0".to_owned()),
+    }
+}
+
+fn main() {
+    let opts = Options::from_args();
+    let result = match opts.command {
+        Command::List { user, since, until } => list(&opts.dir, user.as_deref(), since, until),
+        Command::Play {
+            speed,
+            max_wait,
+            session,
+        } => play(&session_path(&opts.dir, &session), speed, max_wait),
+        Command::Dump { session } => dump(&session_path(&opts.dir, &session)),
+    };
+    if let Err(e) = result {
+        eprintln!("doas-replay: {}", e);
+        std::process::exit(1);
+    }
+}
+
+///Sessions can be given by ID, which is the name of their file in dir, or by the path to it.
+fn session_path(dir: &Path, session: &str) -> PathBuf {
+    if session.contains('/') {
+        PathBuf::from(session)
+    } else {
+        dir.join(format!("{}.cast", session))
+    }
+}
+
+fn list(
+    dir: &Path,
+    user: Option<&str>,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> Result<(), CastError> {
+    let entries = fs::read_dir(dir).map_err(|e| CastError::Io(dir.to_owned(), e))?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "cast"))
+        .collect();
+    //IDs start with the time, so this puts them in the order they were recorded.
+    paths.sort();
+
+    let mut rows = vec![[
+        "SESSION".to_owned(),
+        "STARTED".to_owned(),
+        "USER".to_owned(),
+        "TARGET".to_owned(),
+        "COMMAND".to_owned(),
+    ]];
+    for path in paths {
+        let header = match Cast::open(&path) {
+            Ok(cast) => cast.header,
+            Err(e) => {
+                eprintln!("doas-replay: skipping {}", e);
+                continue;
+            }
+        };
+        let started = Local.timestamp_opt(header.timestamp, 0).single();
+        let date = started.map(|started| started.naive_local().date());
+        if user.map_or(false, |user| user != header.user)
+            || since.map_or(false, |since| date.map_or(true, |date| date < since))
+            || until.map_or(false, |until| date.map_or(true, |date| date > until))
+        {
+            continue;
+        }
+        let Header {
+            session,
+            user,
+            target,
+            command,
+            ..
+        } = header;
+        let started = started.map_or_else(
+            || "?".to_owned(),
+            |started| started.format("%Y-%m-%d %H:%M:%S").to_string(),
+        );
+        rows.push([session, started, user, target, command]);
+    }
+
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+    for [session, started, user, target, command] in rows {
+        println!(
+            "{:w0$}  {:w1$}  {:w2$}  {:w3$}  {}",
+            session,
+            started,
+            user,
+            target,
+            command,
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3]
+        );
+    }
+    Ok(())
+}
+
+fn play(path: &Path, speed: f64, max_wait: Option<f64>) -> Result<(), CastError> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut last = 0.0;
+    for event in Cast::open(path)? {
+        let event = event?;
+        if event.kind != EventKind::Output {
+            continue;
+        }
+        let mut wait = (event.time - last).max(0.0) / speed;
+        if let Some(max_wait) = max_wait {
+            wait = wait.min(max_wait);
+        }
+        last = event.time;
+        thread::sleep(Duration::from_secs_f64(wait));
+        //The terminal going away just means there's nobody left to play to.
+        if stdout
+            .write_all(event.data.as_bytes())
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn dump(path: &Path) -> Result<(), CastError> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut transcript = Transcript::default();
+    for event in Cast::open(path)? {
+        let event = event?;
+        if event.kind == EventKind::Output {
+            let _ = stdout.write_all(transcript.push(&event.data).as_bytes());
+        }
+    }
+    let _ = stdout.write_all(transcript.finish().as_bytes());
+    Ok(())
+}
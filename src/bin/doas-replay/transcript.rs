@@ -0,0 +1,110 @@
+//!This module turns what a session output into plain text, for reading without a terminal.
+//!Escape sequences (colours, cursor movement, titles and so on) are dropped, backspaces rub
+//!out the character before them, and carriage returns only go back to the start of the line,
+//!so what's left is roughly what the terminal showed.
+
+///Turns output into plain text, a piece at a time. Whatever's left over at the end of one piece,
+///such as an unfinished line or escape sequence, is carried on to the next.
+#[derive(Default)]
+pub struct Transcript {
+    ///The line being written, and where on it the cursor is.
+    line: Vec<char>,
+    column: usize,
+    escape: Escape,
+}
+
+///Where we are in an escape sequence.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum Escape {
+    #[default]
+    None,
+    ///Just after ESC.
+    Start,
+    ///After ESC and one or more intermediate bytes, such as ESC ( before the B that ends it.
+    Intermediate,
+    ///In a CSI sequence, such as ESC [ 1 m.
+    Csi,
+    ///In an OSC string, such as ESC ] 0 ; title BEL.
+    Osc,
+    ///After an ESC in an OSC string, which ends it if followed by '\'.
+    OscEsc,
+}
+
+impl Transcript {
+    ///Takes some output, and returns the lines it finished.
+    pub fn push(&mut self, output: &str) -> String {
+        let mut finished = String::new();
+        for c in output.chars() {
+            self.escape = match (self.escape, c) {
+                (Escape::None, '\x1b') => Escape::Start,
+                (Escape::None, c) => {
+                    self.put(c, &mut finished);
+                    Escape::None
+                }
+                (Escape::Start, '[') => Escape::Csi,
+                (Escape::Start, ']') => Escape::Osc,
+                (Escape::Start, '\x20'..='\x2f') | (Escape::Intermediate, '\x20'..='\x2f') => {
+                    Escape::Intermediate
+                }
+                (Escape::Start, _) | (Escape::Intermediate, _) => Escape::None,
+                (Escape::Csi, '\x40'..='\x7e') => Escape::None,
+                (Escape::Csi, _) => Escape::Csi,
+                (Escape::Osc, '\x07') => Escape::None,
+                (Escape::Osc, '\x1b') => Escape::OscEsc,
+                (Escape::OscEsc, '\\') => Escape::None,
+                (Escape::Osc, _) | (Escape::OscEsc, _) => Escape::Osc,
+            };
+        }
+        finished
+    }
+
+    ///Returns the line still being written, if there is one.
+    pub fn finish(self) -> String {
+        if self.line.is_empty() {
+            String::new()
+        } else {
+            self.line.into_iter().chain(Some('\n')).collect()
+        }
+    }
+
+    fn put(&mut self, c: char, finished: &mut String) {
+        match c {
+            '\n' => {
+                finished.extend(self.line.drain(..));
+                finished.push('\n');
+                self.column = 0;
+            }
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => self.write('\t'),
+            c if c.is_control() => {}
+            c => self.write(c),
+        }
+    }
+
+    fn write(&mut self, c: char) {
+        if self.column < self.line.len() {
+            self.line[self.column] = c;
+        } else {
+            self.line.push(c);
+        }
+        self.column += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transcript;
+
+    #[test]
+    fn check_transcript() {
+        let mut transcript = Transcript::default();
+        let mut text = transcript.push("\x1b]0;root@host\x07# \x1b[1ml");
+        text += &transcript.push("s\x1b[0m\r\n");
+        text += &transcript.push("abc\x08\x08X\rY\n\x1b]0;t\x1b\\done\n");
+        text += &transcript.push("\x1b(Bq\x1b)0r\x1b#8s\x1b=t\x1b ");
+        text += &transcript.push("Fu");
+        text += &transcript.finish();
+        assert_eq!(text, "# ls\nYXc\ndone\nqrstu\n");
+    }
+}
@@ -1,33 +1,48 @@
 //!This module is the main module that handles the logic for the doas binary.
 //!It launches programs, parses stuff, and the full jam. This file is fairly straight forward and
 //!boring.
-use crate::parser::rules::Rule;
+use crate::parser::rules::{Cwd, Rule};
+use crate::parser::settings::Umask;
 use crate::Options;
 use nix::unistd;
 
-use std::collections::HashMap;
 use std::env;
+use std::ffi::OsString;
 use std::io;
-use std::os::unix::process::ExitStatusExt;
-use std::path::Path;
-
-use crate::parser;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 
 #[macro_use]
 mod log;
 
+mod audit;
+mod command;
+mod config;
+mod env_filter;
+mod exec;
+mod journald;
 mod persistent_logins;
+mod pty;
+mod recording;
+mod sandbox;
+mod seccomp;
+mod syslog;
 mod user;
 
+use config::{Config, ConfigRule};
 use user::{Password, User};
 
+///The PATH commands are looked up in, and run with, unless the config gives one.
+const SAFE_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
 ///Execute the main doas program.
 pub fn exec_doas(options: &Options, command: &[String]) {
     let current_user = User::from_uid(unistd::Uid::current().as_raw()).unwrap_or_else(|_| {
         err_log!("Couldn't get user with current UID");
         std::process::exit(1);
     });
-    let target_user = User::from_name(options.user.clone()).unwrap_or_else(|_| {
+    let target_name = options.user.as_deref().unwrap_or("root");
+    let target_user = User::from_name(target_name.to_owned()).unwrap_or_else(|_| {
         err_log!("Couldn't find target user");
         std::process::exit(1);
     }); //somehow handle these eventually?
@@ -37,15 +52,37 @@ pub fn exec_doas(options: &Options, command: &[String]) {
     } else {
         Path::new("/etc/doas.conf")
     };
-    let conf_contents = std::fs::read_to_string(conf_path).unwrap_or_else(|e| {
-        err_log!(
-            "couldn't read config file {:?}, exiting. Error: {}",
-            options.config_file,
-            e
-        );
+    let config = if options.config_file.is_some() {
+        //The caller can name any file with -C, so it's read as them, not as root.
+        unistd::setuid(current_user.get_uid()).unwrap_or_else(|e| {
+            err_log!("doas: couldn't drop privileges: {}", e);
+            std::process::exit(1);
+        });
+        Config::load_to_check(conf_path)
+    } else {
+        Config::load(conf_path)
+    };
+    let config = config.unwrap_or_else(|errors| {
+        for e in errors {
+            err_log!("doas: {}", e);
+        }
+        err_log!("couldn't read config file, exiting.");
         std::process::exit(1);
     });
 
+    if options.list {
+        list_rules(&current_user, &config);
+        return;
+    }
+
+    //In login mode the command is the target's shell, which the config has to permit like any other.
+    let login_command;
+    let command = if options.login {
+        login_command = [target_user.get_shell().to_string_lossy().into_owned()];
+        &login_command[..]
+    } else {
+        command
+    };
     let mut cmd = command.iter();
 
     //If there's no command here, the program must of been executed with something that
@@ -54,47 +91,140 @@ pub fn exec_doas(options: &Options, command: &[String]) {
         std::process::exit(0);
     });
     let cmd_args: Vec<_> = cmd.map(|s| s.as_str()).collect();
-    if let (is_allowed, Some(rule)) = check_if_allowed_and_get_rule(
+    //Checking a config with -C doesn't run anything, so there's nothing to audit.
+    let checking = options.config_file.is_some();
+    let mut audit = audit::Audit::new(
+        &current_user,
+        target_user.get_name(),
+        command,
+        config.get_logger(),
+        config.get_audit_log().filter(|_| !checking),
+    );
+    //Rules are matched against the binary that would actually run, which is also the one we run.
+    let mut resolver = command::Resolver::new(cmd_name);
+    if let (is_allowed, Some(config_rule)) = check_if_allowed_and_get_rule(
         &current_user,
         cmd_name,
+        &mut resolver,
         &cmd_args,
-        target_user.get_name(),
-        &conf_contents,
+        (&target_user, options.user.is_some()),
+        &config,
     ) {
+        let rule = config_rule.get_rule();
+        audit.set_rule(config_rule);
         if is_allowed {
+            if options.chdir.is_some() && rule.get_no_chdir() {
+                err_log!(
+                    "doas: the rule at {}:{} doesn't allow choosing the directory with -D",
+                    config_rule.get_file().display(),
+                    config_rule.get_line()
+                );
+                std::process::exit(1);
+            }
             //If a config file was passed, we don't want to run any command - only say they can.
-            if options.config_file != None {
-                log!("Permitted due to config rule.");
+            if checking {
+                log!(
+                    "Permitted due to config rule at {}:{}.",
+                    config_rule.get_file().display(),
+                    config_rule.get_line()
+                );
                 return;
             }
 
-            if !get_and_check_pass_if_needed(&rule, &current_user) {
-                err_log!("doas: Authentication failure");
-                return;
-            };
+            match get_and_check_pass_if_needed(rule, &current_user) {
+                Some(auth) => audit.set_auth(auth),
+                None => {
+                    audit.auth_failed();
+                    err_log!("doas: Authentication failure");
+                    return;
+                }
+            }
 
+            let target_user =
+                rule_target(rule, &current_user, &target_user, options.user.is_some());
+            audit.set_target(target_user.get_name());
+            let search_path = search_path(rule, &config);
+            let seccomp = rule.get_seccomp().map(|name| {
+                let path = seccomp::profile_path(name);
+                seccomp::Profile::load(&path).unwrap_or_else(|e| {
+                    err_log!("doas: couldn't load seccomp profile {}: {}", name, e);
+                    std::process::exit(1);
+                })
+            });
             if !rule.get_no_pass() {}
             set_env_vars(
                 &current_user,
-                &target_user,
+                target_user,
                 command,
-                &options.shell,
-                rule.get_set_env(),
+                options,
+                rule,
+                &search_path,
+                &config,
             );
-            exec_command(cmd_name, &cmd_args, &target_user)
+            env_filter::remove_dangerous_vars(rule.get_allow_dangerous_env());
+            let session = if rule.get_record() {
+                Some(recording::Session::new(
+                    current_user.get_name(),
+                    target_user.get_name(),
+                    &command::quote_command(command),
+                ))
+            } else {
+                None
+            };
+            match resolver.resolve(rule.get_chroot(), &search_path) {
+                Ok(resolved_cmd) => {
+                    audit.permitted(session.as_ref().map(recording::Session::get_id));
+                    exec::Exec::new(resolved_cmd, cmd_name, &cmd_args, target_user)
+                        .digest(rule.get_digest())
+                        .cwd(get_cwd(options, rule, target_user))
+                        .login(options.login)
+                        .umask(get_umask(rule, &config))
+                        .rlimits(rule.get_rlimits())
+                        .nice(rule.get_nice())
+                        .timeout(rule.get_timeout())
+                        .caps(rule.get_caps())
+                        .no_new_privs(rule.get_no_new_privs())
+                        .seccomp(seccomp.as_ref())
+                        .sandbox(rule.get_sandbox())
+                        .chroot(rule.get_chroot())
+                        .use_pty(rule.get_use_pty() || config.get_use_pty())
+                        .record(session.as_ref())
+                        .audit(&audit)
+                        .run()
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    audit.failed("command not found");
+                    err_log!("doas: {}: command not found", cmd_name)
+                }
+                Err(e) => {
+                    audit.failed(&e.to_string());
+                    err_log!("doas: {}: {}", cmd_name, e)
+                }
+            }
+            std::process::exit(1);
         } else {
-            err_log!("Denied due to config rule.");
+            if !checking {
+                audit.denied();
+            }
+            err_log!(
+                "Denied due to config rule at {}:{}.",
+                config_rule.get_file().display(),
+                config_rule.get_line()
+            );
         }
+    } else if !checking {
+        audit.denied();
     }
 }
 
-fn get_and_check_pass_if_needed(rule: &Rule, user: &User) -> bool {
+///Returns how the user authenticated, or None if they couldn't.
+fn get_and_check_pass_if_needed(rule: &Rule, user: &User) -> Option<audit::Auth> {
     if rule.get_no_pass() {
-        return true;
+        return Some(audit::Auth::NoPass);
     }
     //TODO: I should only store UID's as u32.
     if rule.get_persist() && !persistent_logins::need_pass(user.get_uid().as_raw() as i32) {
-        return true;
+        return Some(audit::Auth::Persist);
     };
     //Check for password before execution.
     let user_input = rpassword::read_password_from_tty(Some(&format!(
@@ -103,69 +233,115 @@ fn get_and_check_pass_if_needed(rule: &Rule, user: &User) -> bool {
     )))
     .unwrap();
     if check_pass(&user_input, user.get_password()) != Ok(()) {
-        return false;
-    }
-    true
-}
-
-///Executes the given command.
-fn exec_command(command_name: &str, args: &[&str], target_user: &User) {
-    let mode = nix::sys::stat::Mode::from_bits(0o0022).unwrap(); //default umask for root.
-    nix::sys::stat::umask(mode);
-    unistd::setuid(target_user.get_uid()).unwrap_or_else(|_| panic!("Couldn't set UID"));
-    match std::process::Command::new(command_name).args(args).spawn() {
-        Ok(mut child) => {
-            let exitcode = child.wait().unwrap();
-            if let Some(code) = exitcode.code() {
-                std::process::exit(code);
-            }
-            if exitcode.signal().is_some() {
-                std::process::exit(0);
-            }
-        }
-        Err(e) if e.kind() == io::ErrorKind::NotFound => {
-            err_log!("doas: {}: command not found", command_name)
-        }
-        Err(e) => err_log!("doas: got error: {}\n while running {}", e, command_name),
+        return None;
     }
-    std::process::exit(1);
+    Some(audit::Auth::Password)
 }
 
-///Checks if the command is allowed, and returns a bool and Option<Rule>.
+///Checks if the command is allowed, and returns a bool and the last matching rule.
 ///If no matches were found in the config, it'll return (false, None)
-fn check_if_allowed_and_get_rule(
+///target is the user given with -u, or root, along with whether it was given. Each rule is
+///matched against who its command would run as, see rule_target.
+fn check_if_allowed_and_get_rule<'a>(
     user: &User,
     cmd: &str,
+    resolver: &mut command::Resolver<'_>,
     cmd_args: &[&str],
-    target: &str,
-    config_contents: &str,
-) -> (bool, Option<Rule>) {
+    (target, requested): (&User, bool),
+    config: &'a Config,
+) -> (bool, Option<&'a ConfigRule>) {
     let (mut is_last_match_allowed, mut last_active_rule) = (false, None);
-    for (i, rule) in parser::parse_rules(config_contents).into_iter().enumerate() {
-        let rule = match rule {
-            Ok(rule) => rule,
-            Err(e) => {
-                err_log!(
-                    "Warning:\n Got error in config\n {}\n while working on rule: {} \n Skipping rule.",
-                    e, i
-                );
-                continue;
-            }
-        };
+    for config_rule in config.get_rules() {
+        let rule = config_rule.get_rule();
+        let target = rule_target(rule, user, target, requested);
+        //A rule granting capabilities is there so the command doesn't run as full root.
+        //Running it as root all the same would still own root's files, so it has to be
+        //allowed by name.
+        let names_root = rule
+            .get_targets()
+            .map_or(false, |targets| targets.iter().any(|t| t == "root"));
+        if matches!(rule, Rule::Permit(..))
+            && !rule.get_caps().is_empty()
+            && target.get_uid().is_root()
+            && !names_root
+        {
+            continue;
+        }
+        let search_path = search_path(rule, config);
         if let Some(is_allowed) = rule.is_allowed(
             user.get_name(),
             user.get_groups().iter().map(|g| g.get_name()),
             cmd,
+            resolver
+                .resolve(config_rule.get_rule().get_chroot(), &search_path)
+                .as_deref()
+                .ok(),
             cmd_args,
-            target,
+            target.get_name(),
         ) {
             is_last_match_allowed = is_allowed;
-            last_active_rule = Some(rule);
+            last_active_rule = Some(config_rule);
         }
     }
     (is_last_match_allowed, last_active_rule)
 }
 
+///Returns who a command allowed by rule runs as: target, the user given with -u or else root.
+///A rule granting capabilities is there so the caller doesn't need root, so unless another
+///user was asked for with -u, its command runs as the caller.
+fn rule_target<'a>(rule: &Rule, caller: &'a User, target: &'a User, requested: bool) -> &'a User {
+    if !rule.get_caps().is_empty() && !requested {
+        caller
+    } else {
+        target
+    }
+}
+
+///Returns the PATH a rule's command is looked up in and run with: the rule's own path,
+///or else the global one, or else SAFE_PATH.
+fn search_path(rule: &Rule, config: &Config) -> String {
+    match rule.get_path().or_else(|| config.get_path()) {
+        Some(dirs) => dirs.join(":"),
+        None => SAFE_PATH.to_owned(),
+    }
+}
+
+///Returns the directory to run the command in: the target's home for a login shell,
+///else the one given with -D, else the rule's cwd. None means staying in the caller's directory.
+fn get_cwd(options: &Options, rule: &Rule, target_user: &User) -> Option<PathBuf> {
+    if options.login {
+        return Some(target_user.get_home().to_owned());
+    }
+    options.chdir.clone().or_else(|| {
+        rule.get_cwd().map(|cwd| match cwd {
+            Cwd::Home => target_user.get_home().to_owned(),
+            Cwd::Dir(dir) => PathBuf::from(dir),
+        })
+    })
+}
+
+///Returns the umask to run the command with, from the rule or else the config.
+///None means keeping the caller's, and Some(DEFAULT_UMASK) is used if neither gives one.
+fn get_umask(rule: &Rule, config: &Config) -> Option<u32> {
+    match rule.get_umask().or_else(|| config.get_umask()) {
+        Some(Umask::Preserve) => None,
+        Some(Umask::Mask(mask)) => Some(mask),
+        None => Some(exec::DEFAULT_UMASK),
+    }
+}
+
+///Prints every rule in the config written for the user or one of their groups.
+fn list_rules(user: &User, config: &Config) {
+    for rule in config.get_rules().iter().map(|r| r.get_rule()) {
+        if rule.applies_to(
+            user.get_name(),
+            user.get_groups().iter().map(|g| g.get_name()),
+        ) {
+            println!("{}", rule);
+        }
+    }
+}
+
 ///Check if user input password and hashed password are same.
 fn check_pass(unhashed: &str, maybe_hashed_pass: &Password) -> Result<(), ()> {
     let hashed_pass = match maybe_hashed_pass {
@@ -221,48 +397,67 @@ fn clear_env_vars() {
     }
 }
 
-//TODO: handle set_env instead of just ignoring it.
+///The caller's variables the command keeps, out of vars.
+///With keepenv that's all of them, otherwise only the ones describing their terminal.
+///Either way, the ones doas sets itself are overwritten afterwards.
+fn kept_vars(
+    vars: impl Iterator<Item = (OsString, OsString)>,
+    keep_env: bool,
+) -> Vec<(OsString, OsString)> {
+    vars.filter(|(var, _)| {
+        keep_env
+            || ["LANG", "COLORTERM", "DISPLAY", "TERM"]
+                .iter()
+                .any(|v| var == v)
+    })
+    .collect()
+}
+
 ///Sets the env vars doas works with.
+///Of the variables describing the caller, only the ones the config exports are set.
+///Dangerous variables aren't removed here, see env_filter.
 fn set_env_vars(
     current_user: &User,
     target_user: &User,
     command: &[String],
-    shell: &Option<std::path::PathBuf>,
-    set_env: &HashMap<String, String>,
+    options: &Options,
+    rule: &Rule,
+    path: &str,
+    config: &Config,
 ) {
-    let current_vars = [
-        env::var("LANG"),
-        env::var("COLORTERM"),
-        env::var("DISPLAY"),
-        env::var("TERM"),
-    ];
+    let current_vars = kept_vars(env::vars_os(), rule.get_keep_env() && !options.login);
     clear_env_vars();
-    if let Ok(lang) = &current_vars[0] {
-        env::set_var("LANG", lang);
-    }
-    if let Ok(color_term) = &current_vars[1] {
-        env::set_var("COLORTERM", color_term);
+    for (var, value) in current_vars {
+        env::set_var(var, value);
     }
-    if let Ok(display) = &current_vars[2] {
-        env::set_var("DISPLAY", display);
-    }
-    if let Ok(term) = &current_vars[3] {
-        env::set_var("TERM", term);
-    }
-    env::set_var("SUDO_USER", current_user.get_name());
     env::set_var("USERNAME", current_user.get_name());
-    env::set_var("DOAS_USER", current_user.get_name()); //lol, why the heck not.
-    env::set_var("SUDO_UID", current_user.get_uid().to_string());
-    env::set_var("SUDO_GID", current_user.get_primary_gid().to_string());
-    env::set_var(
-        "SUDO_COMMAND",
-        &command
-            .iter()
-            .fold(String::new(), |acc, command| acc + command),
+    let (name, uid, gid) = (
+        current_user.get_name().to_owned(),
+        current_user.get_uid().to_string(),
+        current_user.get_primary_gid().to_string(),
     );
+    let quoted_command = command::quote_command(command);
+    let mut caller_vars = vec![
+        ("DOAS_USER", name.clone()),
+        ("DOAS_UID", uid.clone()),
+        ("DOAS_GID", gid.clone()),
+        ("DOAS_COMMAND", quoted_command.clone()),
+        ("SUDO_USER", name),
+        ("SUDO_UID", uid),
+        ("SUDO_GID", gid),
+        ("SUDO_COMMAND", quoted_command),
+    ];
+    if let Ok(tty) = unistd::ttyname(io::stdin().as_raw_fd()) {
+        caller_vars.push(("DOAS_TTY", tty.display().to_string()));
+    }
+    for (var, value) in caller_vars {
+        if config.exports(var) {
+            env::set_var(var, value);
+        }
+    }
     env::set_var(
         "HOME",
-        &target_user
+        target_user
             .get_home()
             .to_owned()
             .into_os_string()
@@ -271,18 +466,69 @@ fn set_env_vars(
     );
     env::set_var("USER", target_user.get_name());
     env::set_var("LOGNAME", target_user.get_name());
-    env::set_var(
-        //lol this is hardcoded rn. Fite me.
-        "PATH",
-        "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
-    );
-    let shell = if let Some(shell) = shell {
-        shell
-    } else {
-        current_user.get_shell()
+    env::set_var("PATH", path);
+    let shell = match &options.shell {
+        _ if options.login => target_user.get_shell(),
+        Some(shell) => shell,
+        None => current_user.get_shell(),
     };
     env::set_var("SHELL", shell);
-    for (key, value) in set_env.iter() {
+    for (key, value) in rule.get_set_env().iter() {
         env::set_var(key, value);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_kept_vars() {
+        let vars = || {
+            vec![("TERM", "xterm"), ("EDITOR", "vi"), ("LANG", "C.UTF-8")]
+                .into_iter()
+                .map(|(var, value)| (OsString::from(var), OsString::from(value)))
+        };
+        let names = |keep_env| -> Vec<_> {
+            kept_vars(vars(), keep_env)
+                .into_iter()
+                .map(|(var, _)| var)
+                .collect()
+        };
+        assert_eq!(names(false), vec!["TERM", "LANG"]);
+        assert_eq!(names(true), vec!["TERM", "EDITOR", "LANG"]);
+    }
+
+    #[test]
+    fn check_caps_rule_targets() {
+        let dir = std::env::temp_dir().join(format!("doas-caps-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("doas.conf"),
+            "permit caps { cap_net_raw } nobody cmd true\n\
+             permit caps { cap_net_raw } nobody as root cmd false\n",
+        )
+        .unwrap();
+        let config = Config::load_to_check(&dir.join("doas.conf")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let caller = User::from_name("nobody".to_owned()).unwrap();
+        let root = User::from_uid(0).unwrap();
+        let check = |cmd, requested| {
+            let (allowed, rule) = check_if_allowed_and_get_rule(
+                &caller,
+                cmd,
+                &mut command::Resolver::new(cmd),
+                &[],
+                (&root, requested),
+                &config,
+            );
+            rule.filter(|_| allowed)
+                .map(|rule| rule_target(rule.get_rule(), &caller, &root, requested).get_name())
+        };
+        //Run as the caller, unless -u root is given, which only a rule naming root allows.
+        assert_eq!(check("true", false), Some("nobody"));
+        assert_eq!(check("true", true), None);
+        assert_eq!(check("false", true), Some("root"));
+        assert_eq!(check("false", false), None);
+    }
+}
@@ -0,0 +1,269 @@
+//!This module records every authorization decision doas makes in the system's logs.
+//!Unlike the log! macros, which are for the person running doas, this always happens,
+//!-n or not, so there's a trail of who ran (or tried to run) what.
+//!Decisions go to syslog, or the journal with `logger journald` (or syslog if the journal can't
+//!be reached), and if the config has an `auditlog`, also to that file as one JSON object per
+//!line, along with how each permitted command exited.
+use super::command;
+use super::config::ConfigRule;
+use super::journald;
+use super::syslog::{self, Severity};
+use super::user::User;
+use crate::parser::settings::Logger;
+
+use nix::sys::signal::Signal;
+use nix::unistd;
+use serde_json::{json, Map, Value};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::Duration;
+
+///How the caller proved who they were.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    ///The rule has nopass.
+    NoPass,
+    ///They entered their password.
+    Password,
+    ///They'd entered their password recently enough for a rule with persist.
+    Persist,
+}
+
+impl Auth {
+    fn name(self) -> &'static str {
+        match self {
+            Self::NoPass => "nopass",
+            Self::Password => "password",
+            Self::Persist => "persist",
+        }
+    }
+}
+
+///Who's asking to run what, and from where, along with what's been decided about it so far.
+pub struct Audit {
+    uid: u32,
+    user: String,
+    target: String,
+    argv: Vec<String>,
+    command: String,
+    cwd: String,
+    tty: String,
+    ///The file and line of the rule that decided.
+    rule: Option<(String, usize)>,
+    auth: Option<Auth>,
+    session: Option<String>,
+    logger: Logger,
+    json_log: Option<File>,
+}
+
+impl Audit {
+    ///Starts auditing a request, logging decisions to logger. If json_log is given, events are
+    ///also appended to it. Has to be done as root, to open the log.
+    pub fn new(
+        user: &User,
+        target: &str,
+        command: &[String],
+        logger: Logger,
+        json_log: Option<&Path>,
+    ) -> Self {
+        let json_log = json_log.and_then(|path| {
+            open_json_log(path)
+                .map_err(|e| {
+                    err_log!(
+                        "doas: couldn't open the audit log {}: {}",
+                        path.display(),
+                        e
+                    )
+                })
+                .ok()
+        });
+        Self {
+            uid: user.get_uid().as_raw(),
+            user: user.get_name().to_owned(),
+            target: target.to_owned(),
+            argv: command.to_vec(),
+            command: command::quote_command(command),
+            cwd: std::env::current_dir()
+                .map(|cwd| cwd.display().to_string())
+                .unwrap_or_else(|_| "?".to_owned()),
+            tty: unistd::ttyname(io::stdin().as_raw_fd())
+                .map(|tty| tty.display().to_string())
+                .unwrap_or_else(|_| "none".to_owned()),
+            rule: None,
+            auth: None,
+            session: None,
+            logger,
+            json_log,
+        }
+    }
+
+    ///Changes who the command runs as, for when the rule decides that rather than -u.
+    pub fn set_target(&mut self, target: &str) {
+        self.target = target.to_owned();
+    }
+
+    ///Sets the rule the decision was made by.
+    pub fn set_rule(&mut self, rule: &ConfigRule) {
+        self.rule = Some((rule.get_file().display().to_string(), rule.get_line()));
+    }
+
+    ///Sets how the caller authenticated.
+    pub fn set_auth(&mut self, auth: Auth) {
+        self.auth = Some(auth);
+    }
+
+    ///The command is about to run. session is the ID it's being recorded under, if it is.
+    pub fn permitted(&mut self, session: Option<&str>) {
+        self.session = session.map(str::to_owned);
+        let message = format!("{} ran command {}", self.user, self.describe());
+        self.log(Severity::Info, "permit", &message);
+        self.json("permit", Map::new());
+    }
+
+    ///The command isn't allowed, by the rule set with set_rule or by there being no rule for it.
+    pub fn denied(&self) {
+        let mut message = format!(
+            "command not permitted for {}: {}",
+            self.user,
+            self.describe()
+        );
+        if let Some((file, line)) = &self.rule {
+            message.push_str(&format!(" by rule at {}:{}", file, line));
+        }
+        self.log(Severity::Notice, "deny", &message);
+        self.json("deny", Map::new());
+    }
+
+    pub fn auth_failed(&self) {
+        let message = format!("failed auth for {} running {}", self.user, self.describe());
+        self.log(Severity::Notice, "auth_failure", &message);
+        self.json("auth_failure", Map::new());
+    }
+
+    ///The command was allowed, but couldn't be run, such as when it doesn't exist.
+    pub fn failed(&self, reason: &str) {
+        let message = format!("{} couldn't run {}: {}", self.user, self.describe(), reason);
+        self.log(Severity::Err, "error", &message);
+        let mut fields = Map::new();
+        fields.insert("error".to_owned(), json!(reason));
+        self.json("error", fields);
+    }
+
+    ///The command has exited with status, having been killed by signal if given, after running
+    ///for duration. Only goes to the JSON log.
+    pub fn exited(&self, status: i32, signal: Option<Signal>, duration: Duration) {
+        let mut fields = Map::new();
+        fields.insert("exit_status".to_owned(), json!(status));
+        fields.insert("signal".to_owned(), json!(signal.map(Signal::as_str)));
+        fields.insert("duration".to_owned(), json!(duration.as_secs_f64()));
+        self.json("exit", fields);
+    }
+
+    fn describe(&self) -> String {
+        let mut description = format!(
+            "{} as {} from {} on tty {}",
+            self.command, self.target, self.cwd, self.tty
+        );
+        if let Some(session) = &self.session {
+            description.push_str(&format!(" session {}", session));
+        }
+        description
+    }
+
+    ///Logs an event, described by message, to the logger.
+    ///If the journal can't take it, it goes to syslog instead. If that fails too, it's said on
+    ///stderr even with -n, as it means there's no trail of it, but doas carries on.
+    fn log(&self, severity: Severity, event: &str, message: &str) {
+        let sent = match self.logger {
+            Logger::Syslog => syslog::send(Path::new(syslog::SOCKET), severity, message),
+            Logger::Journald => {
+                let priority = (severity as u8).to_string();
+                let pid = std::process::id().to_string();
+                let mut fields = vec![
+                    ("MESSAGE", message),
+                    ("PRIORITY", &priority),
+                    ("SYSLOG_FACILITY", "10"),
+                    ("SYSLOG_IDENTIFIER", "doas"),
+                    ("SYSLOG_PID", &pid),
+                    ("DOAS_EVENT", event),
+                    ("DOAS_USER", &self.user),
+                    ("DOAS_TARGET", &self.target),
+                    ("DOAS_COMMAND", &self.command),
+                    ("DOAS_CWD", &self.cwd),
+                    ("DOAS_TTY", &self.tty),
+                ];
+                let line;
+                if let Some((file, rule_line)) = &self.rule {
+                    line = rule_line.to_string();
+                    fields.push(("DOAS_RULE_FILE", file));
+                    fields.push(("DOAS_RULE_LINE", &line));
+                }
+                if let Some(session) = &self.session {
+                    fields.push(("DOAS_SESSION", session));
+                }
+                journald::send(Path::new(journald::SOCKET), &fields)
+                    .or_else(|_| syslog::send(Path::new(syslog::SOCKET), severity, message))
+            }
+        };
+        if let Err(e) = sent {
+            eprintln!("doas: couldn't log {} event: {}", event, e);
+        }
+    }
+
+    ///Appends an event to the JSON log, with the fields every event has followed by fields.
+    fn json(&self, event: &str, fields: Map<String, Value>) {
+        let mut json_log = match &self.json_log {
+            Some(json_log) => json_log,
+            None => return,
+        };
+        let mut record = json!({
+            "timestamp": chrono::Local::now().to_rfc3339(),
+            "event": event,
+            "uid": self.uid,
+            "user": self.user,
+            "target": self.target,
+            "command": self.argv,
+            "cwd": self.cwd,
+            "tty": self.tty,
+            "rule": self.rule.as_ref().map(|(file, line)| format!("{}:{}", file, line)),
+            "auth": self.auth.map(Auth::name),
+            "persist_hit": self.auth == Some(Auth::Persist),
+            "session": self.session,
+        });
+        record.as_object_mut().unwrap().extend(fields);
+        //Written in one go, so that records from doas running at the same time don't mix.
+        if let Err(e) = json_log.write_all(format!("{}\n", record).as_bytes()) {
+            eprintln!("doas: couldn't write to the audit log: {}", e);
+        }
+    }
+}
+
+///Opens the JSON log for appending, creating it (and its directory) readable only by root.
+///An existing log has to belong to root and not be writable by anyone else, like the config.
+fn open_json_log(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    //Checked through the file itself, so it's the one that was opened.
+    let metadata = file.metadata()?;
+    if metadata.uid() != 0 || metadata.mode() & 0o022 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "it has to be owned by root and not writable by anyone else",
+        ));
+    }
+    Ok(file)
+}
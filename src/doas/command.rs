@@ -0,0 +1,166 @@
+//!This module finds the binary a command refers to.
+//!Bare names are looked up in the safe PATH doas gives the command, never the caller's,
+//!and the result is canonicalized so rules get matched against the real file.
+//!Since rules can each have their own PATH and chroot, a Resolver remembers what the command
+//!resolved to in every one it's been looked up in.
+//!It also quotes commands back into a line a shell would split the same way.
+use crate::parser::rules::canonicalize_in;
+
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+///Resolves the command to the canonical path of the binary it runs.
+///Names containing a '/' are taken as paths (relative to the current directory),
+///anything else is searched for in the absolute directories of search_path.
+///With a root, the command is looked up inside that chroot, where relative paths are relative
+///to its root and symlinks are followed as they would be in it, and the path returned is the
+///one inside it.
+///This is done as root, so however the command can't be found, the error is NotFound, which
+///doesn't let the caller tell what's in directories they can't read.
+pub fn resolve(name: &str, root: Option<&Path>, search_path: &str) -> io::Result<PathBuf> {
+    let root = root.map(Path::canonicalize).transpose()?;
+    //Finds the binary a path leads to, following symlinks as they'd be followed in the chroot.
+    let find = |path: &Path| {
+        let canonical = match &root {
+            Some(root) => canonicalize_in(root, path).ok()?,
+            None => path.canonicalize().ok()?,
+        };
+        let outside = match &root {
+            Some(root) => root.join(canonical.strip_prefix("/").unwrap_or(&canonical)),
+            None => canonical.clone(),
+        };
+        if is_executable(&outside) {
+            Some(canonical)
+        } else {
+            None
+        }
+    };
+    if name.contains('/') {
+        find(Path::new(name))
+    } else {
+        search_path
+            .split(':')
+            .map(Path::new)
+            //A relative directory would be looked up from wherever the caller happens to be.
+            .filter(|dir| dir.is_absolute())
+            .find_map(|dir| find(&dir.join(name)))
+    }
+    .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+}
+
+///Resolves one command in different chroots and search paths, only looking it up once for each.
+pub struct Resolver<'a> {
+    name: &'a str,
+    resolved: HashMap<(Option<PathBuf>, String), io::Result<PathBuf>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            resolved: HashMap::new(),
+        }
+    }
+
+    ///Resolves the command in search_path, inside root if given. (See resolve.)
+    pub fn resolve(&mut self, root: Option<&Path>, search_path: &str) -> &io::Result<PathBuf> {
+        let name = self.name;
+        self.resolved
+            .entry((root.map(Path::to_owned), search_path.to_owned()))
+            .or_insert_with(|| resolve(name, root, search_path))
+    }
+}
+
+///Returns whether the path is a regular file with an execute bit set.
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+///Joins the command's arguments into a line a POSIX shell would split back into the same args.
+///Arguments with anything but a few safe characters in them are single quoted.
+///Control characters, such as newlines, are escaped with bash's $'...' quoting instead, so the
+///line can't be made to look like more than one when it's logged.
+pub fn quote_command(args: &[String]) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "@%+=:,./_-".contains(c);
+    args.iter()
+        .map(|arg| {
+            if !arg.is_empty() && arg.chars().all(is_safe) {
+                arg.to_owned()
+            } else if arg.chars().any(char::is_control) {
+                format!("$'{}'", arg.chars().map(escape_char).collect::<String>())
+            } else {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+///Escapes a character to go between $'...'.
+fn escape_char(c: char) -> String {
+    match c {
+        '\\' => r"\\".to_owned(),
+        '\'' => r"\'".to_owned(),
+        '\n' => r"\n".to_owned(),
+        '\t' => r"\t".to_owned(),
+        '\r' => r"\r".to_owned(),
+        '\x1b' => r"\e".to_owned(),
+        c if c.is_ascii_control() => format!(r"\x{:02x}", c as u32),
+        c if c.is_control() => format!(r"\u{:04x}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+///Tests for resolving and quoting commands.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn check_resolve_in_chroot() {
+        let root = std::env::temp_dir().join(format!("doas-command-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/env"), "").unwrap();
+        fs::set_permissions(root.join("usr/bin/env"), fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("usr/bin", root.join("bin")).unwrap();
+        symlink("/usr/bin/env", root.join("usr/bin/abs")).unwrap();
+        symlink("../../../../../usr/bin/env", root.join("usr/bin/up")).unwrap();
+        symlink("/etc/passwd", root.join("usr/bin/host")).unwrap();
+        let resolve = |name| resolve(name, Some(&root), "/bin").map_err(|e| e.kind());
+        let results = [
+            resolve("abs"),
+            resolve("up"),
+            resolve("/bin/env"),
+            resolve("bin/../bin/env"),
+            resolve("host"),
+        ];
+        fs::remove_dir_all(&root).unwrap();
+        let env = Ok(PathBuf::from("/usr/bin/env"));
+        assert_eq!(results[..4], [env.clone(), env.clone(), env.clone(), env]);
+        assert_eq!(results[4], Err(io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_quote_command() {
+        let quote = |args: &[&str]| {
+            quote_command(&args.iter().map(|&arg| arg.to_owned()).collect::<Vec<_>>())
+        };
+        assert_eq!(quote(&["ls", "-la"]), "ls -la");
+        assert_eq!(quote(&["echo", "a b", ""]), "echo 'a b' ''");
+        assert_eq!(quote(&["echo", "it's", "$HOME"]), r"echo 'it'\''s' '$HOME'");
+        assert_eq!(
+            quote(&[
+                "echo",
+                "a\nJan  1 00:00:00 doas[1]: it's",
+                "\x1b[2J\x7f\u{85}\\"
+            ]),
+            r"echo $'a\nJan  1 00:00:00 doas[1]: it\'s' $'\e[2J\x7f\u0085\\'"
+        );
+    }
+}
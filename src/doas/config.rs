@@ -0,0 +1,496 @@
+//!This module reads the config from disk.
+//!Besides the main config file, it follows `include "path"` directives and reads the drop-in
+//!directory, /etc/doas.d, in lexical order. Each file is only read once, however many ways
+//!it's reached.
+//!Every file read has to be owned by root and not be writable by anyone else, and every rule
+//!remembers the file and line it came from so errors and logs can point at it.
+//!Global settings, such as `path`, can only be in /etc/doas.conf and the files it includes,
+//!so a drop-in can't change how every other rule behaves.
+//!Aliases are shared by all of the files, wherever in them they're defined.
+//!Like OpenBSD's doas, any mistake in any of the files means the config isn't used at all,
+//!since skipping a broken deny rule would let through what it was there to stop.
+use super::env_filter;
+use crate::parser::{
+    self,
+    aliases::Aliases,
+    pattern::Pattern,
+    rules::Rule,
+    settings::{Logger, Setting, Umask},
+    Entry,
+};
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+///The drop-in directory, read after /etc/doas.conf.
+pub(super) const DROP_IN_DIR: &str = "/etc/doas.d";
+
+///A rule, along with where in the config it was written.
+#[derive(Debug)]
+pub struct ConfigRule {
+    rule: Rule,
+    file: PathBuf,
+    line: usize,
+}
+
+impl ConfigRule {
+    pub fn get_rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    pub fn get_file(&self) -> &Path {
+        &self.file
+    }
+
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+}
+
+///One entry of a config, with its line, once its aliases have been expanded.
+type ParsedEntry = (usize, Result<Entry, String>);
+
+///A file of the config which has been read, but not parsed yet.
+struct Source {
+    path: PathBuf,
+    contents: String,
+    ///Set for drop-ins and what they include, which can't have global settings.
+    drop_in: bool,
+    ///The source read for each of the file's includes, in order. None if the file had already
+    ///been read, or couldn't be.
+    includes: Vec<Option<usize>>,
+}
+
+///Everything read out of the config, in the order it was written.
+#[derive(Debug, Default)]
+pub struct Config {
+    rules: Vec<ConfigRule>,
+    ///The global `path` setting, if any.
+    path: Option<Vec<String>>,
+    ///The global `export` setting, if any.
+    export: Option<Vec<Pattern>>,
+    ///The global `umask` setting, if any.
+    umask: Option<Umask>,
+    ///Set by the global `usepty` setting.
+    use_pty: bool,
+    ///The global `auditlog` setting, if any.
+    audit_log: Option<PathBuf>,
+    ///The global `logger` setting.
+    logger: Logger,
+    ///Whether files have to be owned by root. Only a config given with -C doesn't.
+    check_owner: bool,
+    ///The device and inode of every file read so far.
+    read: Vec<(u64, u64)>,
+    errors: Vec<ConfigError>,
+}
+
+///The ways reading a config file can fail.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    NotOwnedByRoot(PathBuf),
+    Writable(PathBuf),
+    IncludeCycle(Vec<PathBuf>),
+    ///A mistake in a file, with its line.
+    Syntax(PathBuf, usize, String),
+    ///A global setting in a drop-in, with its line.
+    SettingInDropIn(PathBuf, usize),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "couldn't read {}: {}", path.display(), e),
+            Self::NotOwnedByRoot(path) => write!(f, "{} isn't owned by root", path.display()),
+            Self::Writable(path) => write!(
+                f,
+                "{} is writable by users other than its owner",
+                path.display()
+            ),
+            Self::IncludeCycle(paths) => {
+                let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "include cycle: {}", paths.join(" -> "))
+            }
+            Self::Syntax(path, line, e) => write!(f, "{}:{}: {}", path.display(), line, e),
+            Self::SettingInDropIn(path, line) => write!(
+                f,
+                "{}:{}: global settings can only be in the main config, not drop-ins",
+                path.display(),
+                line
+            ),
+        }
+    }
+}
+
+impl Config {
+    ///Reads the config at path, any files it includes, and the drop-in directory.
+    ///Returns every problem found if there are any.
+    pub fn load(path: &Path) -> Result<Self, Vec<ConfigError>> {
+        let mut config = Self {
+            check_owner: true,
+            ..Self::default()
+        };
+        let mut sources = vec![];
+        let mut roots: Vec<_> = config
+            .read_file(&mut sources, path, &mut vec![], false)
+            .into_iter()
+            .collect();
+        match drop_in_files(Path::new(DROP_IN_DIR)) {
+            Ok(fragments) => {
+                for fragment in fragments {
+                    roots.extend(config.read_file(&mut sources, &fragment, &mut vec![], true));
+                }
+            }
+            Err(e) => config.errors.push(e),
+        }
+        config.parse(&sources, &roots);
+        config.finish()
+    }
+
+    ///Reads a config given with -C, and any files it includes, to check it.
+    ///The files don't have to belong to root, and there are no drop-ins.
+    pub fn load_to_check(path: &Path) -> Result<Self, Vec<ConfigError>> {
+        let mut config = Self::default();
+        let mut sources = vec![];
+        let roots: Vec<_> = config
+            .read_file(&mut sources, path, &mut vec![], false)
+            .into_iter()
+            .collect();
+        config.parse(&sources, &roots);
+        config.finish()
+    }
+
+    fn finish(mut self) -> Result<Self, Vec<ConfigError>> {
+        if self.errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    pub fn get_rules(&self) -> &[ConfigRule] {
+        &self.rules
+    }
+
+    pub fn get_path(&self) -> Option<&[String]> {
+        self.path.as_deref()
+    }
+
+    pub fn get_umask(&self) -> Option<Umask> {
+        self.umask
+    }
+
+    ///Returns the file to write the JSON audit log to, if there is one.
+    pub fn get_audit_log(&self) -> Option<&Path> {
+        self.audit_log.as_deref()
+    }
+
+    pub fn get_logger(&self) -> Logger {
+        self.logger
+    }
+
+    ///Returns whether every command should be run on a pty.
+    pub fn get_use_pty(&self) -> bool {
+        self.use_pty
+    }
+
+    ///Returns whether the caller variable var should be set for the command.
+    pub fn exports(&self, var: &str) -> bool {
+        self.export.as_ref().map_or(true, |export| {
+            export.iter().any(|pattern| pattern.matches(var))
+        })
+    }
+
+    ///Reads one file into sources, followed by the files it includes, returning its index.
+    ///stack holds the files currently being read, to catch files including themselves.
+    ///Any problems are added to the config's errors.
+    ///drop_in is set for drop-ins and what they include, which can't have global settings.
+    fn read_file(
+        &mut self,
+        sources: &mut Vec<Source>,
+        path: &Path,
+        stack: &mut Vec<(u64, u64, PathBuf)>,
+        drop_in: bool,
+    ) -> Option<usize> {
+        let file = if self.check_owner {
+            open_checked(path)
+        } else {
+            File::open(path).map_err(|e| ConfigError::Io(path.to_owned(), e))
+        };
+        let (id, contents) = match file.and_then(|file| read_contents(file, path)) {
+            Ok(read) => read,
+            Err(e) => {
+                self.errors.push(e);
+                return None;
+            }
+        };
+        if stack.iter().any(|&(dev, ino, _)| (dev, ino) == id) {
+            let mut cycle: Vec<_> = stack.iter().map(|(_, _, path)| path.clone()).collect();
+            cycle.push(path.to_owned());
+            self.errors.push(ConfigError::IncludeCycle(cycle));
+            return None;
+        }
+        if self.read.contains(&id) {
+            return None;
+        }
+        self.read.push(id);
+
+        let index = sources.len();
+        let includes = parser::includes(&contents);
+        sources.push(Source {
+            path: path.to_owned(),
+            contents,
+            drop_in,
+            includes: vec![],
+        });
+        stack.push((id.0, id.1, path.to_owned()));
+        for include in includes {
+            let include = path
+                .parent()
+                .unwrap_or_else(|| Path::new("/"))
+                .join(include);
+            let read = self.read_file(sources, &include, stack, drop_in);
+            sources[index].includes.push(read);
+        }
+        stack.pop();
+        Some(index)
+    }
+
+    ///Parses every source, with the aliases defined in all of them, then adds what's in the
+    ///roots to the config, along with the files they include where they're included.
+    fn parse(&mut self, sources: &[Source], roots: &[usize]) {
+        let mut aliases = Aliases::new();
+        let entries: Vec<_> = sources
+            .iter()
+            .map(|source| parser::read_entries(&source.contents, &mut aliases))
+            .collect();
+        let mut entries: Vec<Vec<ParsedEntry>> = entries
+            .into_iter()
+            .map(|entries| {
+                parser::expand_entries(entries, &aliases)
+                    .into_iter()
+                    .map(|(line, entry)| (line, entry.map_err(|e| e.to_string())))
+                    .collect()
+            })
+            .collect();
+        for &root in roots {
+            self.add_entries(sources, &mut entries, root);
+        }
+    }
+
+    ///Adds the entries of the source at index to the config, following its includes.
+    fn add_entries(&mut self, sources: &[Source], entries: &mut [Vec<ParsedEntry>], index: usize) {
+        let Source {
+            path,
+            drop_in,
+            includes,
+            ..
+        } = &sources[index];
+        let mut includes = includes.iter();
+        for (line, entry) in std::mem::take(&mut entries[index]) {
+            match entry {
+                Ok(Entry::Rule(rule)) => {
+                    warn_dangerous_env(&rule, path, line);
+                    self.rules.push(ConfigRule {
+                        rule,
+                        file: path.to_owned(),
+                        line,
+                    })
+                }
+                Ok(Entry::Setting(_)) if *drop_in => self
+                    .errors
+                    .push(ConfigError::SettingInDropIn(path.to_owned(), line)),
+                Ok(Entry::Setting(Setting::Path(dirs))) => self.path = Some(dirs),
+                Ok(Entry::Setting(Setting::Export(vars))) => self.export = Some(vars),
+                Ok(Entry::Setting(Setting::Umask(umask))) => self.umask = Some(umask),
+                Ok(Entry::Setting(Setting::UsePty)) => self.use_pty = true,
+                Ok(Entry::Setting(Setting::Logger(logger))) => self.logger = logger,
+                Ok(Entry::Setting(Setting::AuditLog(file))) => {
+                    self.audit_log = Some(PathBuf::from(file))
+                }
+                Ok(Entry::Include(_)) => {
+                    if let Some(&Some(include)) = includes.next() {
+                        self.add_entries(sources, entries, include);
+                    }
+                }
+                Err(e) => self
+                    .errors
+                    .push(ConfigError::Syntax(path.to_owned(), line, e)),
+            }
+        }
+    }
+}
+
+///Warns about setenv giving a dangerous variable the rule doesn't allow, since it'll be dropped.
+fn warn_dangerous_env(rule: &Rule, path: &Path, line: usize) {
+    let mut vars: Vec<_> = rule
+        .get_set_env()
+        .keys()
+        .filter(|var| env_filter::is_dangerous(var))
+        .filter(|var| !rule.get_allow_dangerous_env().contains(var))
+        .collect();
+    vars.sort();
+    for var in vars {
+        err_log!(
+            "Warning:\n {}:{}: setenv sets {}, which is never passed to commands \
+             unless the rule has allow_dangerous_env {}.",
+            path.display(),
+            line,
+            var,
+            var
+        );
+    }
+}
+
+///Returns the *.conf files in the drop-in directory, sorted by name.
+///A missing directory just means there are no drop-ins.
+fn drop_in_files(dir: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(ConfigError::Io(dir.to_owned(), e)),
+    };
+    check_permissions(dir)?;
+    conf_files(dir, entries)
+}
+
+///Returns the *.conf files out of the entries of dir, sorted by name.
+///Symlinks are skipped, so a drop-in can't pull in a file from somewhere else.
+fn conf_files(dir: &Path, entries: fs::ReadDir) -> Result<Vec<PathBuf>, ConfigError> {
+    let mut files = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| ConfigError::Io(dir.to_owned(), e))?;
+        let path = entry.path();
+        //file_type doesn't follow symlinks.
+        let is_file = entry
+            .file_type()
+            .map_err(|e| ConfigError::Io(path.clone(), e))?
+            .is_file();
+        if path.extension().map_or(false, |ext| ext == "conf") && is_file {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+///Opens the file at path, making sure only root could have written it.
+///The check is made on the opened file, so it's the one that gets read.
+pub(super) fn open_checked(path: &Path) -> Result<File, ConfigError> {
+    let file = File::open(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+    let metadata = file
+        .metadata()
+        .map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+    check_metadata(&metadata, path)?;
+    Ok(file)
+}
+
+///Reads an opened config file, returning its device and inode along with what's in it.
+fn read_contents(mut file: File, path: &Path) -> Result<((u64, u64), String), ConfigError> {
+    let metadata = file
+        .metadata()
+        .map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+    Ok(((metadata.dev(), metadata.ino()), contents))
+}
+
+///Makes sure only root could have written the file (or directory) at path.
+pub(super) fn check_permissions(path: &Path) -> Result<(), ConfigError> {
+    let metadata = fs::metadata(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+    check_metadata(&metadata, path)
+}
+
+fn check_metadata(metadata: &fs::Metadata, path: &Path) -> Result<(), ConfigError> {
+    if metadata.uid() != 0 {
+        return Err(ConfigError::NotOwnedByRoot(path.to_owned()));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(ConfigError::Writable(path.to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_load_to_check() {
+        let dir = std::env::temp_dir().join(format!("doas-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("other.conf"), "permit bob\n").unwrap();
+        fs::write(
+            dir.join("doas.conf"),
+            "include other.conf\n include ./other.conf\n permit alice\n",
+        )
+        .unwrap();
+        let config = Config::load_to_check(&dir.join("doas.conf")).unwrap();
+        assert_eq!(config.get_rules().len(), 2);
+
+        fs::write(dir.join("other.conf"), "deny bob cmd\n").unwrap();
+        let errors = Config::load_to_check(&dir.join("doas.conf")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(
+            matches!(&errors[0], ConfigError::Syntax(path, 1, _) if path.ends_with("other.conf")),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn check_aliases_across_files() {
+        let dir = std::env::temp_dir().join(format!("doas-alias-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("aliases.conf"),
+            "alias PKG = /usr/bin/apt\n deny ADMINS cmd PKG\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("doas.conf"),
+            "include aliases.conf\n alias ADMINS = alice\n permit ADMINS cmd PKG\n",
+        )
+        .unwrap();
+        let config = Config::load_to_check(&dir.join("doas.conf")).unwrap();
+        let rules = config.get_rules();
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].get_file().ends_with("aliases.conf"));
+        assert_eq!(
+            rules[0].get_rule().is_allowed(
+                "alice",
+                vec![],
+                "apt",
+                Some(Path::new("/usr/bin/apt")),
+                &[],
+                "root"
+            ),
+            Some(false)
+        );
+
+        fs::write(dir.join("aliases.conf"), "deny ADMIN cmd PKG\n").unwrap();
+        let errors = Config::load_to_check(&dir.join("doas.conf")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].contains("aliases.conf:1: Alias \"ADMIN\""));
+        assert!(errors[1].contains("doas.conf:3: Alias \"PKG\""));
+    }
+
+    #[test]
+    fn check_drop_in_files() {
+        let dir = std::env::temp_dir().join(format!("doas-drop-in-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("b.conf"), "permit bob\n").unwrap();
+        fs::write(dir.join("a.conf"), "permit alice\n").unwrap();
+        fs::write(dir.join("notes.txt"), "permit carol\n").unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", dir.join("c.conf")).unwrap();
+        let files = conf_files(&dir, fs::read_dir(&dir).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, vec![dir.join("a.conf"), dir.join("b.conf")]);
+    }
+}
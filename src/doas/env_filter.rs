@@ -0,0 +1,73 @@
+//!This module keeps dangerous variables out of the command's environment.
+//!Variables like LD_PRELOAD or BASH_ENV let whoever sets them run code as the target user, so
+//!they're removed whether they came from the caller (keepenv) or the rule (setenv), unless the
+//!rule names them in `allow_dangerous_env`.
+use std::env;
+
+///Variables that are never passed to a command. A trailing '*' matches any suffix.
+const DANGEROUS_ENV_VARS: &[&str] = &[
+    "LD_*",
+    "DYLD_*",
+    "BASH_ENV",
+    "ENV",
+    "BASH_FUNC_*",
+    "SHELLOPTS",
+    "BASHOPTS",
+    "PS4",
+    "IFS",
+    "CDPATH",
+    "GLOBIGNORE",
+    "PYTHONPATH",
+    "PYTHONHOME",
+    "PYTHONSTARTUP",
+    "PERL5LIB",
+    "PERL5OPT",
+    "PERLLIB",
+    "RUBYLIB",
+    "RUBYOPT",
+    "NODE_OPTIONS",
+    "NODE_PATH",
+    "GCONV_PATH",
+    "LOCPATH",
+    "NLSPATH",
+    "HOSTALIASES",
+    "RESOLV_HOST_CONF",
+    "MALLOC_*",
+];
+
+///Returns whether the variable is on the denylist.
+pub fn is_dangerous(var: &str) -> bool {
+    DANGEROUS_ENV_VARS
+        .iter()
+        .any(|denied| match denied.strip_suffix('*') {
+            Some(prefix) => var.starts_with(prefix),
+            None => var == *denied,
+        })
+}
+
+///Removes every dangerous variable from the environment, except the allowed ones.
+pub fn remove_dangerous_vars(allowed: &[String]) {
+    for (var, _) in env::vars_os() {
+        if let Some(var) = var.to_str() {
+            if is_dangerous(var) && !allowed.iter().any(|a| a == var) {
+                env::remove_var(var);
+            }
+        }
+    }
+}
+
+///Tests for the denylist.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_dangerous() {
+        assert!(is_dangerous("LD_PRELOAD"));
+        assert!(is_dangerous("LD_LIBRARY_PATH"));
+        assert!(is_dangerous("BASH_FUNC_ls%%"));
+        assert!(is_dangerous("IFS"));
+        assert!(!is_dangerous("ENVIRONMENT"));
+        assert!(!is_dangerous("TERM"));
+    }
+}
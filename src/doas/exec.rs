@@ -0,0 +1,686 @@
+//!This module runs the permitted command.
+//!doas forks, and the child sets itself up and execs the exact binary the rules were matched
+//!against, while the parent waits on it and exits with its status.
+//!The parent never stops being root, so the command can't kill it or stop it from logging.
+//!If the child can't run the command, it tells the parent why over a pipe, for the audit log.
+//!When a rule pins a digest, the binary is opened once, hashed through that fd and then
+//!executed through the same fd, so it can't be swapped out in between.
+//!When a rule grants capabilities, every other capability is dropped before the UID changes,
+//!and the granted ones are put in the ambient set so the command keeps them over execve.
+//!With a chroot, path is the command's path inside of it.
+//!A command with a timeout is always run on a pty if the caller has a terminal.
+//!With a pty, the parent relays between it and the caller's terminal while waiting,
+//!recording what goes through if the session is being recorded.
+use super::audit::Audit;
+use super::config;
+use super::pty::{self, Pty};
+use super::recording::{Recording, Session};
+use super::sandbox;
+use super::seccomp::{self, Profile};
+use super::user::User;
+use crate::parser::caps::Capability;
+use crate::parser::limits::{self, Resource, Rlimit};
+use crate::parser::rules::{Digest, Sandbox};
+
+use nix::fcntl::OFlag;
+use nix::sys::signal::{self, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{self, ForkResult, Pid};
+use sha2::{Digest as _, Sha256};
+
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+///The umask commands run with, unless the config gives one.
+pub const DEFAULT_UMASK: u32 = 0o022;
+
+///How long a command that timed out gets to exit after SIGTERM, before it's sent SIGKILL.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+///What doas exits with when the command timed out, the same as timeout(1).
+const TIMEOUT_STATUS: i32 = 124;
+
+///The command to run, and how to set up the process it runs in.
+///Built up with the methods below, then started with run.
+pub struct Exec<'a> {
+    path: &'a Path,
+    command_name: &'a str,
+    args: &'a [&'a str],
+    target_user: &'a User,
+    digest: Option<&'a Digest>,
+    cwd: Option<PathBuf>,
+    login: bool,
+    ///None keeps the caller's umask.
+    umask: Option<u32>,
+    rlimits: &'a [Rlimit],
+    nice: Option<i32>,
+    timeout: Option<Duration>,
+    caps: &'a [Capability],
+    no_new_privs: bool,
+    seccomp: Option<&'a Profile>,
+    sandbox: Sandbox,
+    chroot: Option<&'a Path>,
+    use_pty: bool,
+    record: Option<&'a Session>,
+    audit: Option<&'a Audit>,
+}
+
+impl<'a> Exec<'a> {
+    ///Runs the binary at path as target_user, with command_name as its argv[0].
+    pub fn new(
+        path: &'a Path,
+        command_name: &'a str,
+        args: &'a [&'a str],
+        target_user: &'a User,
+    ) -> Self {
+        Self {
+            path,
+            command_name,
+            args,
+            target_user,
+            digest: None,
+            cwd: None,
+            login: false,
+            umask: Some(DEFAULT_UMASK),
+            rlimits: &[],
+            nice: None,
+            timeout: None,
+            caps: &[],
+            no_new_privs: false,
+            seccomp: None,
+            sandbox: Sandbox::default(),
+            chroot: None,
+            use_pty: false,
+            record: None,
+            audit: None,
+        }
+    }
+
+    ///If digest is given, the binary's contents have to hash to it.
+    pub fn digest(self, digest: Option<&'a Digest>) -> Self {
+        Self { digest, ..self }
+    }
+
+    ///If cwd is given, the command runs in it rather than the caller's directory.
+    pub fn cwd(self, cwd: Option<PathBuf>) -> Self {
+        Self { cwd, ..self }
+    }
+
+    ///If login is set, the command is started as a login shell, with a '-' in front of argv[0].
+    pub fn login(self, login: bool) -> Self {
+        Self { login, ..self }
+    }
+
+    ///Sets the umask to run the command with, or None to keep the caller's.
+    pub fn umask(self, umask: Option<u32>) -> Self {
+        Self { umask, ..self }
+    }
+
+    ///Sets the resource limits the command runs with.
+    pub fn rlimits(self, rlimits: &'a [Rlimit]) -> Self {
+        Self { rlimits, ..self }
+    }
+
+    ///Sets the nice level the command runs with, if given.
+    pub fn nice(self, nice: Option<i32>) -> Self {
+        Self { nice, ..self }
+    }
+
+    ///If timeout is given, the command is killed once it's been running that long.
+    pub fn timeout(self, timeout: Option<Duration>) -> Self {
+        Self { timeout, ..self }
+    }
+
+    ///If caps isn't empty, the command runs with only those capabilities.
+    pub fn caps(self, caps: &'a [Capability]) -> Self {
+        Self { caps, ..self }
+    }
+
+    ///If no_new_privs is set, the command can't gain privileges by running setuid binaries.
+    pub fn no_new_privs(self, no_new_privs: bool) -> Self {
+        Self {
+            no_new_privs,
+            ..self
+        }
+    }
+
+    ///If a profile is given, the command runs under it. (This implies no_new_privs.)
+    pub fn seccomp(self, seccomp: Option<&'a Profile>) -> Self {
+        Self { seccomp, ..self }
+    }
+
+    ///Sets the namespaces the command runs in.
+    pub fn sandbox(self, sandbox: Sandbox) -> Self {
+        Self { sandbox, ..self }
+    }
+
+    ///If a chroot is given, the command runs inside it, starting in its root directory.
+    pub fn chroot(self, chroot: Option<&'a Path>) -> Self {
+        Self { chroot, ..self }
+    }
+
+    ///If use_pty is set and the caller has a terminal, the command gets a pty instead of it.
+    pub fn use_pty(self, use_pty: bool) -> Self {
+        Self { use_pty, ..self }
+    }
+
+    ///If a session is given, the command is run on a pty and everything on it is recorded.
+    pub fn record(self, record: Option<&'a Session>) -> Self {
+        Self { record, ..self }
+    }
+
+    ///Records how the command exited in audit.
+    pub fn audit(self, audit: &'a Audit) -> Self {
+        Self {
+            audit: Some(audit),
+            ..self
+        }
+    }
+
+    ///Executes the command, and exits with its status.
+    ///doas itself stays root, so the command can't signal it to get out of being timed out,
+    ///recorded or audited. Everything that changes who the process is happens in the child.
+    pub fn run(self) -> ! {
+        let fail = |e: String| -> ! {
+            err_log!("doas: {}", e);
+            if let Some(audit) = self.audit {
+                audit.failed(&e);
+            }
+            std::process::exit(1);
+        };
+        //Whatever doesn't go through the pty would go around the recording, such as input
+        //piped in, so a recorded command has to be run from a terminal.
+        if self.record.is_some() && !pty::all_terminals() {
+            fail(format!(
+                "{} can only be run from a terminal, as it's recorded",
+                self.command_name
+            ));
+        }
+        //Opened first, as /dev/ptmx and the session directory may not be reachable after the
+        //sandbox or chroot.
+        //A command with a timeout gets a pty too, so it runs in the foreground of a terminal
+        //of its own, and ^C on the caller's terminal reaches it rather than doas.
+        let pty = if self.use_pty || self.record.is_some() || self.timeout.is_some() {
+            pty::open().unwrap_or_else(|e| {
+                fail(format!(
+                    "couldn't open a pty for {}: {}",
+                    self.command_name, e
+                ))
+            })
+        } else {
+            None
+        };
+        let recording = self.record.map(|session| {
+            let recording = Recording::create(session, pty.as_ref().map(Pty::get_size))
+                .unwrap_or_else(|e| {
+                    fail(format!(
+                        "couldn't record session to {}: {}",
+                        session.get_path().display(),
+                        e
+                    ))
+                });
+            err_log!("doas: recording this session as {}", session.get_id());
+            recording
+        });
+        //The child writes what went wrong to this if it can't run the command. Once it's
+        //executed the command, the pipe is closed without anything written to it.
+        let (errors, errors_write) = unistd::pipe2(OFlag::O_CLOEXEC)
+            .unwrap_or_else(|e| fail(format!("couldn't create a pipe: {}", e)));
+
+        let start = Instant::now();
+        match unistd::fork() {
+            Ok(ForkResult::Child) => {
+                let _ = unistd::close(errors);
+                let e = self.exec(pty.as_ref());
+                let mut errors_write = unsafe { File::from_raw_fd(errors_write) };
+                let _ = errors_write.write_all(e.as_bytes());
+                std::process::exit(1);
+            }
+            Ok(ForkResult::Parent { child }) => {
+                let _ = unistd::close(errors_write);
+                let mut e = String::new();
+                let _ = unsafe { File::from_raw_fd(errors) }.read_to_string(&mut e);
+                if !e.is_empty() {
+                    let _ = waitpid(child, None);
+                    fail(e);
+                }
+                wait_for(
+                    child,
+                    self.command_name,
+                    self.timeout,
+                    pty,
+                    recording,
+                    self.audit,
+                    start,
+                )
+            }
+            Err(e) => fail(format!("couldn't fork: {}", e)),
+        }
+    }
+
+    ///Sets up the process the command runs in, then executes it. Run in the child.
+    ///Only returns if the command couldn't be run, with why.
+    fn exec(&self, pty: Option<&Pty>) -> String {
+        let command_name = self.command_name;
+        //With a timeout, the command gets its own process group so that everything
+        //it starts can be killed along with it. Attaching to a pty already does that.
+        if let Some(pty) = pty {
+            if let Err(e) = pty.attach() {
+                return format!("couldn't attach {} to its pty: {}", command_name, e);
+            }
+        } else if self.timeout.is_some() {
+            let _ = unistd::setpgid(Pid::from_raw(0), Pid::from_raw(0));
+        }
+        if let Some(umask) = self.umask {
+            nix::sys::stat::umask(nix::sys::stat::Mode::from_bits_truncate(umask));
+        }
+        //These have to be set while still root, since lowering a hard limit can't be undone
+        //and only root can raise its priority.
+        if let Err(e) = set_limits(self.rlimits, self.nice) {
+            return format!("couldn't limit {}: {}", command_name, e);
+        }
+        if let Err(e) = sandbox::enter(self.sandbox) {
+            return format!("couldn't sandbox {}: {}", command_name, e);
+        }
+        if self.sandbox != Sandbox::default() {
+            //Otherwise a command run as root could just unmount or remount its way out.
+            if let Err(e) = drop_cap(CAP_SYS_ADMIN) {
+                return format!("couldn't sandbox {}: {}", command_name, e);
+            }
+        }
+        if let Some(root) = self.chroot {
+            if let Err(e) = enter_chroot(root) {
+                return format!("couldn't chroot to {}: {}", root.display(), e);
+            }
+        }
+        if !self.caps.is_empty() {
+            if let Err(e) = limit_caps(self.caps) {
+                return format!("couldn't drop capabilities for {}: {}", command_name, e);
+            }
+        }
+        let target_user = self.target_user;
+        if let Err(e) = unistd::setuid(target_user.get_uid()) {
+            return format!("couldn't become {}: {}", target_user.get_name(), e);
+        }
+        if !self.caps.is_empty() {
+            if let Err(e) = raise_ambient_caps(self.caps) {
+                return format!("couldn't grant capabilities for {}: {}", command_name, e);
+            }
+        }
+
+        //This is done as the target user, so they have to be able to get into the directory.
+        if let Some(cwd) = &self.cwd {
+            if let Err(e) = unistd::chdir(cwd) {
+                return format!(
+                    "couldn't change directory to {} as {}: {}",
+                    cwd.display(),
+                    target_user.get_name(),
+                    e
+                );
+            }
+        }
+
+        let path = self.path;
+        let binary = match self.digest.map(|digest| open_and_verify(path, digest)) {
+            Some(Err(e)) => return format!("{}: {}", path.display(), e),
+            Some(Ok(binary)) => Some(binary),
+            None => None,
+        };
+
+        let argv0 = if self.login {
+            let name = Path::new(command_name).file_name().unwrap_or_default();
+            format!("-{}", name.to_string_lossy())
+        } else {
+            command_name.to_owned()
+        };
+        let argv: Vec<CString> = std::iter::once(argv0.as_str())
+            .chain(self.args.iter().copied())
+            .map(|arg| CString::new(arg).expect("args can't contain nul bytes"))
+            .collect();
+        let argv: Vec<_> = argv.iter().map(|arg| arg.as_c_str()).collect();
+        if let Err(e) = restrict(self.no_new_privs, self.seccomp) {
+            return format!("couldn't restrict {}: {}", command_name, e);
+        }
+        let e = match &binary {
+            Some(binary) => {
+                let env = current_env();
+                let env: Vec<_> = env.iter().map(|var| var.as_c_str()).collect();
+                unistd::fexecve(binary.as_raw_fd(), &argv, &env).unwrap_err()
+            }
+            None => {
+                let path = CString::new(path.as_os_str().to_owned().into_vec())
+                    .expect("paths can't contain nul bytes");
+                unistd::execv(&path, &argv).unwrap_err()
+            }
+        };
+        match e.as_errno() {
+            Some(nix::errno::Errno::ENOENT) => format!("{}: command not found", command_name),
+            _ => format!("got error: {}\n while running {}", e, command_name),
+        }
+    }
+}
+
+///Chroots into root, after checking only root could have changed anything in it, or in any
+///directory on the way to it, which could otherwise have been moved aside for another.
+fn enter_chroot(root: &Path) -> Result<(), String> {
+    let root = root.canonicalize().map_err(|e| e.to_string())?;
+    for dir in root.ancestors() {
+        config::check_permissions(dir).map_err(|e| e.to_string())?;
+    }
+    unistd::chroot(&root).map_err(|e| e.to_string())?;
+    unistd::chdir("/").map_err(|e| e.to_string())
+}
+
+///Sets no_new_privs and installs the seccomp profile, if given.
+///The profile goes last, so it only has to allow what the command itself needs.
+fn restrict(no_new_privs: bool, seccomp: Option<&Profile>) -> io::Result<()> {
+    if no_new_privs {
+        seccomp::set_no_new_privs()?;
+    }
+    if let Some(profile) = seccomp {
+        profile.install()?;
+    }
+    Ok(())
+}
+
+///Waits on the child and exits with its status, relaying its I/O if it's on a pty.
+///How it exited, and how long it ran for since start, go in the audit log.
+///If there's a timeout and the child is still running after it, its process group is sent
+///SIGTERM, then SIGKILL if any of the group is still around KILL_GRACE later. doas exits with
+///TIMEOUT_STATUS once that's done, even if the command itself went on SIGTERM.
+fn wait_for(
+    child: Pid,
+    command_name: &str,
+    timeout: Option<Duration>,
+    pty: Option<Pty>,
+    recording: Option<Recording>,
+    audit: Option<&Audit>,
+    start: Instant,
+) -> ! {
+    let exit = |status: i32, signal: Option<Signal>| -> ! {
+        if let Some(audit) = audit {
+            audit.exited(status, signal, start.elapsed());
+        }
+        std::process::exit(status)
+    };
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let mut killer = None;
+    if let Some(timeout) = timeout {
+        //Also done in the child, whichever runs first makes sure the group exists to signal.
+        //On a pty the child starts a session, which it can't do once it's a group leader.
+        if pty.is_none() {
+            let _ = unistd::setpgid(child, child);
+        }
+        let timed_out = Arc::clone(&timed_out);
+        killer = Some(thread::spawn(move || {
+            thread::sleep(timeout);
+            timed_out.store(true, Ordering::SeqCst);
+            let group = Pid::from_raw(-child.as_raw());
+            let _ = signal::kill(group, Signal::SIGTERM);
+            let deadline = Instant::now() + KILL_GRACE;
+            //Checked on until the whole group is gone, not just the command.
+            while Instant::now() < deadline && signal::kill(group, None).is_ok() {
+                thread::sleep(Duration::from_millis(100));
+            }
+            let _ = signal::kill(group, Signal::SIGKILL);
+        }));
+    }
+    let status = match pty {
+        Some(pty) => pty.relay(child, recording),
+        None => waitpid(child, None),
+    };
+    if timed_out.load(Ordering::SeqCst) {
+        //The rest of the group may have ignored SIGTERM, and would outlive doas.
+        if let Some(killer) = killer {
+            let _ = killer.join();
+        }
+        err_log!(
+            "doas: {} timed out after {}, killed it",
+            command_name,
+            limits::display_timeout(timeout.unwrap())
+        );
+        exit(TIMEOUT_STATUS, None);
+    }
+    match status {
+        Ok(WaitStatus::Exited(_, code)) => exit(code, None),
+        Ok(WaitStatus::Signaled(_, signal, _)) => exit(0, Some(signal)),
+        Ok(status) => {
+            err_log!("doas: unexpected status {:?} from {}", status, command_name);
+            exit(1, None);
+        }
+        Err(e) => {
+            err_log!("doas: couldn't wait on {}: {}", command_name, e);
+            exit(1, None);
+        }
+    }
+}
+
+///Applies the resource limits (as both soft and hard limits) and nice level to this process.
+fn set_limits(rlimits: &[Rlimit], nice: Option<i32>) -> io::Result<()> {
+    for rlimit in rlimits {
+        let limit = rlimit.limit.unwrap_or(libc::RLIM_INFINITY);
+        let limit = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: limit,
+        };
+        let resource = match rlimit.resource {
+            Resource::As => libc::RLIMIT_AS,
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::Data => libc::RLIMIT_DATA,
+            Resource::Fsize => libc::RLIMIT_FSIZE,
+            Resource::Memlock => libc::RLIMIT_MEMLOCK,
+            Resource::Nofile => libc::RLIMIT_NOFILE,
+            Resource::Nproc => libc::RLIMIT_NPROC,
+            Resource::Stack => libc::RLIMIT_STACK,
+        };
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if let Some(nice) = nice {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+///Drops every capability but caps from the bounding set, so nothing run later can get them
+///back, and stops UID 0 from regaining them all on execve. Has to be done as root.
+///The capabilities are also kept over the coming setuid.
+fn limit_caps(caps: &[Capability]) -> io::Result<()> {
+    //Capabilities the kernel doesn't know about fail with EINVAL, which is where to stop.
+    for number in 0.. {
+        if caps.iter().any(|cap| cap.number() == number) {
+            continue;
+        }
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, number as libc::c_ulong, 0, 0, 0) } != 0 {
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::EINVAL) => break,
+                e => return Err(e),
+            }
+        }
+    }
+    let securebits = SECBIT_NOROOT | SECBIT_NOROOT_LOCKED;
+    if unsafe { libc::prctl(libc::PR_SET_SECUREBITS, securebits, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+///Makes caps the only permitted, effective and inheritable capabilities, then raises them in the
+///ambient set, which is what the command gets after execve.
+fn raise_ambient_caps(caps: &[Capability]) -> io::Result<()> {
+    let mut data = [CapUserData::default(); 2];
+    for cap in caps {
+        let (word, bit) = ((cap.number() / 32) as usize, 1 << (cap.number() % 32));
+        data[word].permitted |= bit;
+        data[word].effective |= bit;
+        data[word].inheritable |= bit;
+    }
+    let header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for cap in caps {
+        let number = cap.number() as libc::c_ulong;
+        let raise = libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong;
+        if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, raise, number, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+///Drops the capability numbered number from the bounding and inheritable sets, so the command
+///doesn't get it on execve, even as root. Has to be done as root.
+fn drop_cap(number: u32) -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, number as libc::c_ulong, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapUserData::default(); 2];
+    if unsafe { libc::syscall(libc::SYS_capget, &header, data.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    data[(number / 32) as usize].inheritable &= !(1 << (number % 32));
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+///From linux/capability.h.
+const CAP_SYS_ADMIN: u32 = 21;
+
+///From linux/securebits.h: UID 0 doesn't get every capability on execve, and that can't change.
+const SECBIT_NOROOT: libc::c_ulong = 1 << 0;
+const SECBIT_NOROOT_LOCKED: libc::c_ulong = 1 << 1;
+
+///From linux/capability.h, the version using two words per set.
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+///Opens the binary and checks its contents hash to the digest.
+///The returned file is what should be executed, rather than the path.
+///A script is run by its interpreter opening /dev/fd/N, so for one the file is left open over
+///execve, or the interpreter wouldn't find it.
+fn open_and_verify(path: &Path, digest: &Digest) -> io::Result<File> {
+    let mut binary = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+    let mut is_script = None;
+    loop {
+        let read = binary.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        is_script.get_or_insert_with(|| buf[..read].starts_with(b"#!"));
+        hasher.update(&buf[..read]);
+    }
+    let actual: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if actual != digest.as_hex() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "digest mismatch, refusing to run. Expected {} but the binary is sha256:{}",
+                digest, actual
+            ),
+        ));
+    }
+    if is_script == Some(true) && unsafe { libc::fcntl(binary.as_raw_fd(), libc::F_SETFD, 0) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(binary)
+}
+
+///The current environment as KEY=value strings, to hand to fexecve.
+fn current_env() -> Vec<CString> {
+    std::env::vars_os()
+        .map(|(key, value)| {
+            let mut var: OsString = key;
+            var.push("=");
+            var.push(value);
+            CString::new(var.into_vec()).expect("env vars can't contain nul bytes")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{self, Entry};
+
+    ///Reads a capability set, such as CapAmb, out of /proc/self/status.
+    fn cap_set(name: &str) -> u64 {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{}:", name)))
+            .unwrap();
+        u64::from_str_radix(line.trim(), 16).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs root, run with cargo test -- --ignored"]
+    fn check_caps_after_setuid() {
+        //Only root can change UID, which a user namespace made without it can't get around,
+        //as it could only have the one UID.
+        assert!(unistd::geteuid().is_root(), "has to be run as root");
+        let caps = match parser::parse_config("permit caps { cap_net_raw } root").pop() {
+            Some((_, Ok(Entry::Rule(rule)))) => rule.get_caps().to_vec(),
+            entry => panic!("{:?}", entry),
+        };
+        let expected = 1 << caps[0].number();
+        match unistd::fork().unwrap() {
+            ForkResult::Child => {
+                let worked = limit_caps(&caps).is_ok()
+                    && unistd::setuid(unistd::Uid::from_raw(65534)).is_ok()
+                    && raise_ambient_caps(&caps).is_ok()
+                    && cap_set("CapAmb") == expected
+                    && cap_set("CapBnd") == expected
+                    && cap_set("CapPrm") == expected;
+                std::process::exit(if worked { 0 } else { 1 })
+            }
+            ForkResult::Parent { child } => {
+                assert_eq!(waitpid(child, None), Ok(WaitStatus::Exited(child, 0)))
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//!This module sends entries to the systemd journal, using its native protocol.
+//!An entry is a datagram of fields, each written as `KEY=value\n`. A value with a newline in it
+//!is written as `KEY\n`, then its length as a little endian u64, then the value and a `\n`.
+//!Unlike with syslog, every field can be searched on, such as with `journalctl DOAS_USER=alice`.
+//!An entry too big for a datagram is written to a sealed memfd instead, which is passed to
+//!journald in an otherwise empty datagram, as sd_journal_send does.
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, SealFlag};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+///The socket journald listens on for native entries.
+pub const SOCKET: &str = "/run/systemd/journal/socket";
+
+///Sends an entry made up of fields to the journal listening on socket.
+///Field names have to be upper case letters, digits and underscores, not starting with one.
+pub fn send(socket: &Path, fields: &[(&str, &str)]) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    let entry = encode(fields);
+    match datagram.send_to(&entry, socket) {
+        Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => send_memfd(&datagram, socket, &entry),
+        result => result.map(|_| ()),
+    }
+}
+
+///Sends entry to socket in a memfd, sealed so it can't be changed once journald has it.
+fn send_memfd(datagram: &UnixDatagram, socket: &Path, entry: &[u8]) -> io::Result<()> {
+    let to_io = |e: nix::Error| io::Error::from(e.as_errno().unwrap_or(Errno::EIO));
+    let name = CString::new("doas-journal").expect("the name has no nul bytes");
+    let flags = MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING;
+    let mut memfd = unsafe { File::from_raw_fd(memfd_create(&name, flags).map_err(to_io)?) };
+    memfd.write_all(entry)?;
+    let seals = SealFlag::F_SEAL_SHRINK
+        | SealFlag::F_SEAL_GROW
+        | SealFlag::F_SEAL_WRITE
+        | SealFlag::F_SEAL_SEAL;
+    fcntl(memfd.as_raw_fd(), FcntlArg::F_ADD_SEALS(seals)).map_err(to_io)?;
+    //Connected rather than addressed, as nix's Unix addresses can't be passed to sendmsg safely.
+    datagram.connect(socket)?;
+    let fds = [memfd.as_raw_fd()];
+    let rights = [ControlMessage::ScmRights(&fds)];
+    sendmsg(datagram.as_raw_fd(), &[], &rights, MsgFlags::empty(), None).map_err(to_io)?;
+    Ok(())
+}
+
+fn encode(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    for (key, value) in fields {
+        entry.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_send() {
+        let dir = std::env::temp_dir().join(format!("doas-journald-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("socket");
+        let journal = UnixDatagram::bind(&socket).unwrap();
+        send(
+            &socket,
+            &[
+                ("PRIORITY", "6"),
+                ("DOAS_USER", "alice"),
+                ("DOAS_COMMAND", "printf 'a\nb'"),
+            ],
+        )
+        .unwrap();
+        let mut buf = [0; 1024];
+        let read = journal.recv(&mut buf).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut expected = b"PRIORITY=6\nDOAS_USER=alice\nDOAS_COMMAND\n".to_vec();
+        expected.extend_from_slice(&12u64.to_le_bytes());
+        expected.extend_from_slice(b"printf 'a\nb'\n");
+        assert_eq!(&buf[..read], &expected[..]);
+    }
+
+    #[test]
+    fn check_send_memfd() {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let dir = std::env::temp_dir().join(format!("doas-memfd-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("socket");
+        let journal = UnixDatagram::bind(&socket).unwrap();
+        let command = "x".repeat(400_000);
+        send(&socket, &[("DOAS_COMMAND", &command)]).unwrap();
+        //Received with libc, as nix's recvmsg can't be trusted with the sender's address either.
+        let mut buf = [0u8; 16];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut _,
+            iov_len: buf.len(),
+        };
+        let mut control = [0u64; 8];
+        let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+        header.msg_iov = &mut iov;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr() as *mut _;
+        header.msg_controllen = std::mem::size_of_val(&control) as _;
+        let read = unsafe { libc::recvmsg(journal.as_raw_fd(), &mut header, 0) };
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read, 0);
+        let cmsg = unsafe { libc::CMSG_FIRSTHDR(&header) };
+        assert!(!cmsg.is_null());
+        assert_eq!(unsafe { (*cmsg).cmsg_type }, libc::SCM_RIGHTS);
+        let fd = unsafe { *(libc::CMSG_DATA(cmsg) as *const libc::c_int) };
+        let mut memfd = unsafe { File::from_raw_fd(fd) };
+        //The fd shares its offset with the one written through, so it's at the end.
+        memfd.seek(SeekFrom::Start(0)).unwrap();
+        let mut entry = String::new();
+        memfd.read_to_string(&mut entry).unwrap();
+        assert_eq!(entry, format!("DOAS_COMMAND={}\n", command));
+        assert!(memfd.write_all(b"more").is_err());
+    }
+}
@@ -0,0 +1,342 @@
+//!This module runs the command on a pseudo-terminal of its own, for rules with `usepty`.
+//!Otherwise the command shares the caller's terminal, and could use TIOCSTI to push input into
+//!it which the caller's shell would read and run once doas exits. With a pty, doas sits in
+//!between, copying what's typed on the caller's terminal to the command and its output back.
+//!The caller's terminal is put in raw mode meanwhile, so that the pty does the line editing and
+//!turns ^C into a signal for the command. It's restored once the command exits.
+//!The command is in a session of its own, and doas isn't, so the kernel won't let ^Z on the pty
+//!(or anything else but SIGSTOP) stop it. doas stops it for ^Z itself, then stops along with it.
+use super::recording::Recording;
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{openpty, Winsize};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::termios::{self, LocalFlags, SetArg, SpecialCharacterIndices, Termios};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{self, Pid};
+
+use std::convert::TryFrom;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+const STDIN: RawFd = 0;
+
+///The signals doas handles while relaying. SIGHUP and SIGTERM are passed on to the command,
+///so that doas outlives it and gets to restore the terminal. SIGTSTP stops the command, as ^Z
+///does, so that doas only stops once the command has.
+const SIGNALS: &[Signal] = &[
+    Signal::SIGCHLD,
+    Signal::SIGWINCH,
+    Signal::SIGHUP,
+    Signal::SIGTERM,
+    Signal::SIGTSTP,
+];
+
+///The write end of the pipe the signal handler hands signals to the relay loop through.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+///A pty for the command, along with the caller's terminal it gets relayed to.
+pub struct Pty {
+    master: RawFd,
+    slave: RawFd,
+    ///Where the command's output goes.
+    terminal: RawFd,
+    size: Winsize,
+}
+
+///Opens a pty set up like the caller's terminal.
+///Returns None if none of stdin, stdout and stderr are a terminal, since the command then
+///has no terminal to get at anyway.
+pub fn open() -> nix::Result<Option<Pty>> {
+    let terminal = match [1, 2, STDIN].iter().find(|&&fd| is_terminal(fd)) {
+        Some(&terminal) => terminal,
+        None => return Ok(None),
+    };
+    let modes = termios::tcgetattr(terminal)?;
+    let size = window_size(terminal)?;
+    let pty = openpty(&size, &modes)?;
+    for &fd in &[pty.master, pty.slave] {
+        nix::fcntl::fcntl(fd, nix::fcntl::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC))?;
+    }
+    Ok(Some(Pty {
+        master: pty.master,
+        slave: pty.slave,
+        terminal,
+        size,
+    }))
+}
+
+impl Pty {
+    ///The size of the caller's terminal when the pty was opened.
+    pub fn get_size(&self) -> Winsize {
+        self.size
+    }
+
+    ///Makes the pty the controlling terminal of the command, in place of whichever of stdin,
+    ///stdout and stderr were the caller's terminal. Run in the child, before exec.
+    ///The command ends up in a session (and so process group) of its own.
+    pub fn attach(&self) -> nix::Result<()> {
+        unistd::setsid()?;
+        if unsafe { libc::ioctl(self.slave, libc::TIOCSCTTY as _, 0) } != 0 {
+            return Err(nix::Error::last());
+        }
+        for fd in 0..=2 {
+            if is_terminal(fd) {
+                unistd::dup2(self.slave, fd)?;
+            }
+        }
+        Ok(())
+    }
+
+    ///Relays between the caller's terminal and the pty until child exits, and returns its status.
+    ///Everything relayed is also written to the recording, if there is one. Run in the parent.
+    ///If the command is stopped, such as by ^Z, doas stops too, and continues it once it's
+    ///continued itself. Even if relaying fails, child has been waited on by the time this returns.
+    pub fn relay(self, child: Pid, recording: Option<Recording>) -> nix::Result<WaitStatus> {
+        //Otherwise the master never sees the command's side get closed.
+        let _ = unistd::close(self.slave);
+        let status = self.relay_until_exit(child, recording);
+        if status.is_err() {
+            //This hangs up on the command, so it isn't left waiting on doas to read its output.
+            let _ = unistd::close(self.master);
+            let _ = waitpid(child, None);
+        }
+        status
+    }
+
+    fn relay_until_exit(
+        &self,
+        child: Pid,
+        mut recording: Option<Recording>,
+    ) -> nix::Result<WaitStatus> {
+        let (signals, signal_pipe) = unistd::pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+        SIGNAL_PIPE.store(signal_pipe, Ordering::SeqCst);
+        let action = SigAction::new(
+            SigHandler::Handler(on_signal),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        for &sig in SIGNALS {
+            unsafe { signal::sigaction(sig, &action) }?;
+        }
+        //The command may have exited before the handler was in place.
+        on_signal(Signal::SIGCHLD as libc::c_int);
+
+        let mut raw = if is_terminal(STDIN) {
+            Some(RawMode::enter(STDIN)?)
+        } else {
+            None
+        };
+        let mut buf = [0; 8192];
+        let mut input = is_terminal(STDIN);
+        loop {
+            let mut fds = vec![
+                PollFd::new(signals, PollFlags::POLLIN),
+                PollFd::new(self.master, PollFlags::POLLIN),
+            ];
+            if input {
+                fds.push(PollFd::new(STDIN, PollFlags::POLLIN));
+            }
+            match poll(&mut fds, -1) {
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                result => result?,
+            };
+            let ready = |fd: &PollFd| !fd.revents().unwrap_or_else(PollFlags::empty).is_empty();
+
+            if ready(&fds[0]) {
+                while let Ok(1) = unistd::read(signals, &mut buf[..1]) {
+                    match Signal::try_from(buf[0] as libc::c_int) {
+                        Ok(Signal::SIGCHLD) => match waitpid(
+                            child,
+                            Some(WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED),
+                        )? {
+                            WaitStatus::StillAlive => {}
+                            WaitStatus::Stopped(..) => {
+                                //The caller's terminal has to be usable while doas is stopped.
+                                let was_raw = raw.take().is_some();
+                                suspend()?;
+                                if was_raw {
+                                    raw = Some(RawMode::enter(STDIN)?);
+                                }
+                                if let Ok(size) = window_size(self.terminal) {
+                                    let _ = set_window_size(self.master, &size);
+                                }
+                                //The command leads its process group, so this is all of it.
+                                signal::kill(Pid::from_raw(-child.as_raw()), Signal::SIGCONT)?;
+                            }
+                            status => {
+                                self.drain(&mut recording);
+                                return Ok(status);
+                            }
+                        },
+                        Ok(Signal::SIGWINCH) => {
+                            if let Ok(size) = window_size(self.terminal) {
+                                let _ = set_window_size(self.master, &size);
+                                if let Some(recording) = &mut recording {
+                                    let _ = recording.resize(size);
+                                }
+                            }
+                        }
+                        Ok(Signal::SIGTSTP) => self.stop_foreground(),
+                        Ok(sig) => {
+                            let _ = signal::kill(child, sig);
+                        }
+                        Err(_) => {}
+                    }
+                }
+            }
+            if ready(&fds[1]) && !self.copy_output(&mut buf, &mut recording) {
+                //Everything on the command's side has closed the pty, so it's done or about to be.
+                return waitpid(child, None);
+            }
+            if input && ready(&fds[2]) {
+                match unistd::read(STDIN, &mut buf) {
+                    Ok(0) | Err(_) => input = false,
+                    Ok(read) => {
+                        let suspend = self.suspend_char();
+                        let typed: Vec<_> = buf[..read]
+                            .iter()
+                            .copied()
+                            .filter(|&byte| Some(byte) != suspend)
+                            .collect();
+                        write_all(self.master, &typed)?;
+                        if let Some(recording) = &mut recording {
+                            let _ = recording.input(&buf[..read]);
+                        }
+                        if typed.len() < read {
+                            self.stop_foreground();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    ///Returns the character that stops the command, ^Z unless it's been changed, if the pty
+    ///turns it into a signal at the moment.
+    fn suspend_char(&self) -> Option<u8> {
+        let modes = termios::tcgetattr(self.master).ok()?;
+        let suspend = modes.control_chars[SpecialCharacterIndices::VSUSP as usize];
+        if modes.local_flags.contains(LocalFlags::ISIG) && suspend != 0 {
+            Some(suspend)
+        } else {
+            None
+        }
+    }
+
+    ///Stops the foreground process group of the pty, which the pty itself can't.
+    ///If that's the command's, doas finds out it's stopped, and stops too.
+    fn stop_foreground(&self) {
+        let mut group: libc::pid_t = 0;
+        if unsafe { libc::ioctl(self.master, libc::TIOCGPGRP, &mut group) } == 0 && group > 0 {
+            let _ = signal::kill(Pid::from_raw(-group), Signal::SIGSTOP);
+        }
+    }
+
+    ///Copies what output the command has written to the caller's terminal.
+    ///Returns false once the pty has been closed by the command's side.
+    fn copy_output(&self, buf: &mut [u8], recording: &mut Option<Recording>) -> bool {
+        match unistd::read(self.master, buf) {
+            Ok(0) | Err(_) => false,
+            Ok(read) => {
+                if let Some(recording) = recording {
+                    let _ = recording.output(&buf[..read]);
+                }
+                write_all(self.terminal, &buf[..read]).is_ok()
+            }
+        }
+    }
+
+    ///Copies what output is left once the command has exited.
+    fn drain(&self, recording: &mut Option<Recording>) {
+        let mut buf = [0; 8192];
+        loop {
+            let mut fds = [PollFd::new(self.master, PollFlags::POLLIN)];
+            match poll(&mut fds, 0) {
+                Ok(1) if self.copy_output(&mut buf, recording) => {}
+                _ => break,
+            }
+        }
+    }
+}
+
+///Puts the terminal in raw mode, and puts it back how it was when dropped.
+struct RawMode {
+    fd: RawFd,
+    saved: Termios,
+}
+
+impl RawMode {
+    fn enter(fd: RawFd) -> nix::Result<Self> {
+        let saved = termios::tcgetattr(fd)?;
+        let mut raw = saved.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, SetArg::TCSADRAIN, &raw)?;
+        Ok(Self { fd, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(self.fd, SetArg::TCSADRAIN, &self.saved);
+    }
+}
+
+///Stops doas until it's continued, as ^Z would have. If whatever ran doas doesn't do job
+///control, this does nothing, the same as ^Z.
+///doas handles SIGTSTP itself while relaying, so the default action is put back first.
+fn suspend() -> nix::Result<()> {
+    let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    let handler = unsafe { signal::sigaction(Signal::SIGTSTP, &default) }?;
+    let raised = signal::raise(Signal::SIGTSTP);
+    unsafe { signal::sigaction(Signal::SIGTSTP, &handler) }?;
+    raised
+}
+
+extern "C" fn on_signal(sig: libc::c_int) {
+    let byte = sig as u8;
+    let fd = SIGNAL_PIPE.load(Ordering::SeqCst);
+    unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+}
+
+///Returns whether stdin, stdout and stderr are all terminals, and so would all go through a pty.
+pub fn all_terminals() -> bool {
+    (0..=2).all(is_terminal)
+}
+
+fn is_terminal(fd: RawFd) -> bool {
+    unistd::isatty(fd).unwrap_or(false)
+}
+
+fn window_size(fd: RawFd) -> nix::Result<Winsize> {
+    let mut size = Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return Err(nix::Error::last());
+    }
+    Ok(size)
+}
+
+fn set_window_size(fd: RawFd, size: &Winsize) -> nix::Result<()> {
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, size) } != 0 {
+        return Err(nix::Error::last());
+    }
+    Ok(())
+}
+
+fn write_all(fd: RawFd, mut buf: &[u8]) -> nix::Result<()> {
+    while !buf.is_empty() {
+        match unistd::write(fd, buf) {
+            Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(e) => return Err(e),
+            Ok(written) => buf = &buf[written..],
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//!This module records sessions run by rules with the `record` option, so they can be audited later.
+//!Each session goes in its own file in SESSION_DIR, named after its ID, in asciicast v2 format:
+//!a JSON header on the first line, then one `[seconds, code, data]` line per event, where code is
+//!"o" for output, "i" for input, and "r" for the terminal being resized to "COLSxROWS".
+//!On top of what asciicast asks for, the header has a "doas" object saying whose session it was.
+//!The session is recorded off the command's pty, so a recorded command has to be run with stdin,
+//!stdout and stderr all on a terminal, or some of it would go unrecorded.
+use nix::pty::Winsize;
+use serde_json::json;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+///Where recorded sessions are kept.
+pub const SESSION_DIR: &str = "/var/log/doas/sessions";
+
+///The extension session files have.
+pub const EXTENSION: &str = "cast";
+
+///Who ran what, for a session about to be recorded.
+pub struct Session {
+    id: String,
+    user: String,
+    target: String,
+    command: String,
+    cwd: Option<PathBuf>,
+}
+
+impl Session {
+    ///Describes a new session, with an ID made up of the time it started and doas' PID,
+    ///such as 20200921-134502-4123.
+    pub fn new(user: &str, target: &str, command: &str) -> Self {
+        Self {
+            id: format!(
+                "{}-{}",
+                chrono::Local::now().format("%Y%m%d-%H%M%S"),
+                std::process::id()
+            ),
+            user: user.to_owned(),
+            target: target.to_owned(),
+            command: command.to_owned(),
+            cwd: std::env::current_dir().ok(),
+        }
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    ///The file this session is recorded to.
+    pub fn get_path(&self) -> PathBuf {
+        Path::new(SESSION_DIR).join(format!("{}.{}", self.id, EXTENSION))
+    }
+}
+
+///A session being recorded.
+pub struct Recording {
+    file: File,
+    start: Instant,
+    ///The end of the last output or input, if it stopped partway through a UTF-8 character.
+    pending_output: Vec<u8>,
+    pending_input: Vec<u8>,
+}
+
+impl Recording {
+    ///Creates the session's file and writes its header. Has to be done as root, since only
+    ///root can write to SESSION_DIR (and read what's in it).
+    pub fn create(session: &Session, size: Option<Winsize>) -> io::Result<Self> {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(SESSION_DIR)?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(session.get_path())?;
+        let (width, height) = size.map_or((80, 24), |size| (size.ws_col, size.ws_row));
+        let header = json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": chrono::Utc::now().timestamp(),
+            "title": session.command,
+            "env": {
+                "TERM": std::env::var("TERM").ok(),
+                "SHELL": std::env::var("SHELL").ok(),
+            },
+            "doas": {
+                "session": session.id,
+                "user": session.user,
+                "target": session.target,
+                "command": session.command,
+                "cwd": session.cwd,
+            },
+        });
+        writeln!(file, "{}", header)?;
+        Ok(Self {
+            file,
+            start: Instant::now(),
+            pending_output: Vec::new(),
+            pending_input: Vec::new(),
+        })
+    }
+
+    ///Records output from the command.
+    pub fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        let data = take_utf8(&mut self.pending_output, data);
+        self.event("o", &data)
+    }
+
+    ///Records input to the command.
+    pub fn input(&mut self, data: &[u8]) -> io::Result<()> {
+        let data = take_utf8(&mut self.pending_input, data);
+        self.event("i", &data)
+    }
+
+    ///Records the terminal being resized.
+    pub fn resize(&mut self, size: Winsize) -> io::Result<()> {
+        self.event("r", &format!("{}x{}", size.ws_col, size.ws_row))
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let time = self.start.elapsed().as_secs_f64();
+        //Written in one go, so a line is never left half written.
+        let line = format!("{}\n", json!([(time * 1e6).round() / 1e6, code, data]));
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+///Appends data to pending, and takes as much of it as is complete UTF-8, leaving a character
+///cut off at the end in pending for next time. Invalid bytes become U+FFFD.
+fn take_utf8(pending: &mut Vec<u8>, data: &[u8]) -> String {
+    pending.extend_from_slice(data);
+    let mut text = String::new();
+    let mut rest = &pending[..];
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                rest = &[];
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).expect("checked by from_utf8"));
+                match e.error_len() {
+                    Some(invalid) => {
+                        text.push('\u{fffd}');
+                        rest = &after[invalid..];
+                    }
+                    //Cut off at the end, rather than invalid.
+                    None => {
+                        rest = after;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    *pending = rest.to_vec();
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::take_utf8;
+
+    #[test]
+    fn check_take_utf8() {
+        let mut pending = Vec::new();
+        let snowman = "☃".as_bytes();
+        assert_eq!(take_utf8(&mut pending, b"a"), "a");
+        assert_eq!(take_utf8(&mut pending, &snowman[..1]), "");
+        assert_eq!(take_utf8(&mut pending, &snowman[1..]), "☃");
+        assert_eq!(take_utf8(&mut pending, b"\xffb"), "\u{fffd}b");
+        assert!(pending.is_empty());
+        let mut chunk = b"\xff\xfeb".to_vec();
+        chunk.extend_from_slice(&snowman[..2]);
+        assert_eq!(take_utf8(&mut pending, &chunk), "\u{fffd}\u{fffd}b");
+        assert_eq!(take_utf8(&mut pending, &snowman[2..]), "☃");
+        assert!(pending.is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+//!This module puts the command in its own namespaces, for rules with sandbox options.
+//!`private-tmp` gives it an empty tmpfs over /tmp, `readonly-root` makes every mount read-only
+//!(besides that /tmp), and `no-network` gives it a network namespace with nothing but a
+//!loopback device, which is left down.
+//!None of this is visible outside of the command, and it all goes away when it exits.
+//!The command also loses CAP_SYS_ADMIN, so it can't remount or unmount its way back out.
+//!These options are there to stop a command from doing what it shouldn't by mistake, not to
+//!contain a command run as root that sets out to escape: it can still write to disk devices,
+//!or reach the rest of the system through /proc/1/root, for instance.
+use crate::parser::rules::Sandbox;
+
+use nix::errno::Errno;
+use nix::mount::{mount, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::unistd;
+
+use std::fs;
+
+///Moves this process into new namespaces as the sandbox asks for. Has to be done as root.
+pub fn enter(sandbox: Sandbox) -> nix::Result<()> {
+    let mut flags = CloneFlags::empty();
+    if sandbox.private_tmp || sandbox.readonly_root {
+        flags |= CloneFlags::CLONE_NEWNS;
+    }
+    if sandbox.no_network {
+        flags |= CloneFlags::CLONE_NEWNET;
+    }
+    if flags.is_empty() {
+        return Ok(());
+    }
+    unshare(flags)?;
+    if !flags.contains(CloneFlags::CLONE_NEWNS) {
+        return Ok(());
+    }
+
+    //Otherwise the mounts below would propagate back out to the rest of the system.
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )?;
+    if sandbox.readonly_root {
+        remount_readonly()?;
+    }
+    if sandbox.private_tmp {
+        mount(
+            Some("tmpfs"),
+            "/tmp",
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some("mode=1777"),
+        )?;
+    }
+    //The current directory still points into the mounts that were just covered up,
+    //so relative paths would get around them unless it's looked up again.
+    let cwd = unistd::getcwd()?;
+    unistd::chdir(&cwd)
+}
+
+///Makes every mount read-only. The mounts are this namespace's own copies, so this only
+///changes them for the command.
+///Remounting a bind mount changes the flags of just that mount, which are otherwise kept,
+///so nosuid and the like stay set.
+fn remount_readonly() -> nix::Result<()> {
+    let mounts = fs::read_to_string("/proc/self/mountinfo")
+        .map_err(|e| nix::Error::Sys(Errno::from_i32(e.raw_os_error().unwrap_or(0))))?;
+    for (path, mut flags) in visible_mounts(&mounts) {
+        flags |= MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+        match mount(
+            None::<&str>,
+            path.as_str(),
+            None::<&str>,
+            flags,
+            None::<&str>,
+        ) {
+            //A mount that's been mounted over can't be reached, by the command either.
+            Err(nix::Error::Sys(Errno::ENOENT)) | Err(nix::Error::Sys(Errno::EINVAL)) => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+///Returns the mount points in mountinfo, with the flags of the mount that can be reached at each.
+///When mounts are stacked at one path, that's the last one, the ones before it are mounted over.
+fn visible_mounts(mountinfo: &str) -> Vec<(String, MsFlags)> {
+    let mut visible: Vec<(String, MsFlags)> = vec![];
+    for (path, flags) in mountinfo.lines().filter_map(parse_mount) {
+        visible.retain(|(visible, _)| *visible != path);
+        visible.push((path, flags));
+    }
+    visible
+}
+
+///Parses a line of /proc/self/mountinfo into the mount point and the flags on it.
+fn parse_mount(line: &str) -> Option<(String, MsFlags)> {
+    let mut fields = line.split(' ').skip(4);
+    let path = unescape(fields.next()?);
+    let flags = fields
+        .next()?
+        .split(',')
+        .map(|option| match option {
+            "nosuid" => MsFlags::MS_NOSUID,
+            "nodev" => MsFlags::MS_NODEV,
+            "noexec" => MsFlags::MS_NOEXEC,
+            "noatime" => MsFlags::MS_NOATIME,
+            "nodiratime" => MsFlags::MS_NODIRATIME,
+            "relatime" => MsFlags::MS_RELATIME,
+            _ => MsFlags::empty(),
+        })
+        .collect();
+    Some((path, flags))
+}
+
+///Undoes the octal escapes mountinfo uses for spaces and such in paths, like \040.
+fn unescape(path: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        match after {
+            [a @ b'0'..=b'3', b @ b'0'..=b'7', c @ b'0'..=b'7', after @ ..] if byte == b'\\' => {
+                bytes.push(((a - b'0') << 6) | ((b - b'0') << 3) | (c - b'0'));
+                rest = after;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = after;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::ForkResult;
+
+    #[test]
+    fn check_parse_mount() {
+        assert_eq!(
+            parse_mount("29 28 254:16 / /mnt/a\\040b ro,nosuid,nodev,relatime - ext4 /dev/vdb ro"),
+            Some((
+                "/mnt/a b".to_owned(),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RELATIME
+            ))
+        );
+        assert_eq!(unescape("\\134\\0\\477"), "\\\\0\\477");
+        assert_eq!(
+            visible_mounts(
+                "1 0 8:1 / / rw - ext4 /dev/sda1 rw\n\
+                 2 1 0:5 / /mnt ro,nosuid - tmpfs tmpfs ro\n\
+                 3 2 0:6 / /mnt rw,noexec - tmpfs tmpfs rw"
+            ),
+            vec![
+                ("/".to_owned(), MsFlags::empty()),
+                ("/mnt".to_owned(), MsFlags::MS_NOEXEC)
+            ]
+        );
+    }
+
+    ///Becomes root in a new user namespace, unless already root, so namespaces can be made
+    ///without privileges. Has to be done in a process with only one thread.
+    fn become_root() -> bool {
+        let (uid, gid) = (unistd::geteuid(), unistd::getegid());
+        uid.is_root()
+            || (unshare(CloneFlags::CLONE_NEWUSER).is_ok()
+                && fs::write("/proc/self/setgroups", "deny").is_ok()
+                && fs::write("/proc/self/uid_map", format!("0 {} 1", uid)).is_ok()
+                && fs::write("/proc/self/gid_map", format!("0 {} 1", gid)).is_ok())
+    }
+
+    ///Mounts a tmpfs at dir, then another over it with flags, in a namespace of its own.
+    fn stack_mounts(dir: &str, flags: MsFlags) -> bool {
+        let tmpfs = |flags| mount(Some("tmpfs"), dir, Some("tmpfs"), flags, None::<&str>);
+        unshare(CloneFlags::CLONE_NEWNS).is_ok()
+            && mount(
+                None::<&str>,
+                "/",
+                None::<&str>,
+                MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                None::<&str>,
+            )
+            .is_ok()
+            && tmpfs(MsFlags::empty()).is_ok()
+            && tmpfs(flags).is_ok()
+    }
+
+    #[test]
+    fn check_readonly_root() {
+        let sandbox = Sandbox {
+            private_tmp: true,
+            readonly_root: true,
+            no_network: false,
+        };
+        //Outside of /tmp, which the sandbox covers up.
+        let stacked = format!("/var/tmp/doas-sandbox-test-{}", std::process::id());
+        fs::create_dir(&stacked).unwrap();
+        match unistd::fork().unwrap() {
+            ForkResult::Child => {
+                let set_up = become_root() && stack_mounts(&stacked, MsFlags::MS_NOEXEC);
+                let before = visible_mounts(&fs::read_to_string("/proc/self/mountinfo").unwrap());
+                let entered = enter(sandbox).is_ok();
+                let after = visible_mounts(&fs::read_to_string("/proc/self/mountinfo").unwrap());
+                let kept_flags = after.iter().all(|(path, flags)| {
+                    before
+                        .iter()
+                        .filter(|(before, _)| before == path && path != "/tmp")
+                        .all(|(_, before)| flags.contains(*before))
+                });
+                //Only the last mount at each path can be reached, the rest are mounted over.
+                let mut mounts: Vec<(String, bool)> = vec![];
+                for line in fs::read_to_string("/proc/self/mountinfo").unwrap().lines() {
+                    let mut fields = line.split(' ').skip(4);
+                    let path = fields.next().unwrap().to_owned();
+                    let readonly = fields.next().unwrap().split(',').any(|o| o == "ro");
+                    mounts.retain(|(p, _)| *p != path);
+                    mounts.push((path, readonly));
+                }
+                let worked = set_up
+                    && entered
+                    && kept_flags
+                    && after.iter().any(|(path, flags)| {
+                        *path == stacked && flags.contains(MsFlags::MS_NOEXEC)
+                    })
+                    && mounts
+                        .iter()
+                        .all(|(path, readonly)| *readonly || path == "/tmp")
+                    && fs::write("/tmp/doas-sandbox-test", "").is_ok()
+                    && fs::write("/dev/shm/doas-sandbox-test", "").is_err();
+                std::process::exit(if worked { 0 } else { 1 })
+            }
+            ForkResult::Parent { child } => {
+                let status = waitpid(child, None);
+                fs::remove_dir(&stacked).unwrap();
+                assert_eq!(status, Ok(WaitStatus::Exited(child, 0)))
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//!This module loads seccomp profiles, and installs them as BPF filters before a command runs.
+//!A rule picks a profile with `seccomp "name"`, which is read from the seccomp directory inside
+//!the drop-in directory (/etc/doas.d/seccomp/name), and like the config has to be owned by root.
+//!Profiles are lines of a default action and syscall lists, such as:
+//!```text
+//!#Comments start with a '#'.
+//!default allow
+//!deny ptrace mount umount2
+//!kill kexec_load init_module
+//!```
+//!`allow` lets the syscall run, `deny` makes it fail with EPERM and `kill` kills the command.
+//!If no default is given, anything not listed is denied.
+//!Syscalls are given by name, without the SYS_ prefix, or by number for ones too new to have a
+//!name here, such as rseq.
+//!The profile is installed before the command is executed, so it has to allow `execve`, and
+//!`execveat` too if the rule pins a digest, as the command is then run through its fd.
+use super::config::{self, ConfigError};
+
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+///The directory profiles are read from, inside the drop-in directory.
+const PROFILE_DIR: &str = "seccomp";
+
+///What happens when a syscall is made.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    Allow,
+    ///Fail with EPERM.
+    Deny,
+    ///Kill the whole process.
+    Kill,
+}
+
+impl Action {
+    fn parse(action: &str) -> Option<Self> {
+        match action {
+            "allow" => Some(Self::Allow),
+            "deny" => Some(Self::Deny),
+            "kill" => Some(Self::Kill),
+            _ => None,
+        }
+    }
+
+    fn seccomp_ret(self) -> u32 {
+        match self {
+            Self::Allow => SECCOMP_RET_ALLOW,
+            Self::Deny => libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            Self::Kill => libc::SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+}
+
+///A parsed profile: the action for each listed syscall, and for everything else.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Profile {
+    default: Action,
+    syscalls: Vec<(libc::c_long, Action)>,
+}
+
+///The ways loading a profile can fail.
+#[derive(Debug)]
+pub enum SeccompError {
+    Config(ConfigError),
+    ///A mistake in the profile, with its line.
+    Syntax(PathBuf, usize, String),
+    Unsupported,
+}
+
+impl fmt::Display for SeccompError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(e) => write!(f, "{}", e),
+            Self::Syntax(path, line, e) => write!(f, "{}:{}: {}", path.display(), line, e),
+            Self::Unsupported => write!(f, "seccomp profiles aren't supported on this platform"),
+        }
+    }
+}
+
+///Returns where the profile of the given name is.
+pub fn profile_path(name: &str) -> PathBuf {
+    Path::new(config::DROP_IN_DIR).join(PROFILE_DIR).join(name)
+}
+
+impl Profile {
+    ///Reads the profile at path, which has to be owned by root and not writable by others.
+    pub fn load(path: &Path) -> Result<Self, SeccompError> {
+        if SYSCALLS.is_empty() {
+            return Err(SeccompError::Unsupported);
+        }
+        let mut contents = String::new();
+        config::open_checked(path)
+            .map_err(SeccompError::Config)?
+            .read_to_string(&mut contents)
+            .map_err(|e| SeccompError::Config(ConfigError::Io(path.to_owned(), e)))?;
+        Self::parse(&contents).map_err(|(line, e)| SeccompError::Syntax(path.to_owned(), line, e))
+    }
+
+    ///Parses a profile, returning the line and description of the first mistake in it.
+    fn parse(contents: &str) -> Result<Self, (usize, String)> {
+        let mut profile = Self {
+            default: Action::Deny,
+            syscalls: vec![],
+        };
+        for (i, line) in contents.lines().enumerate() {
+            let mut words = line.split('#').next().unwrap_or("").split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            if keyword == "default" {
+                profile.default = match (words.next().and_then(Action::parse), words.next()) {
+                    (Some(action), None) => action,
+                    _ => return Err((i + 1, "expected default allow, deny or kill".to_owned())),
+                };
+                continue;
+            }
+            let action = Action::parse(keyword).ok_or_else(|| {
+                (
+                    i + 1,
+                    format!("expected default, allow, deny or kill, got \"{}\"", keyword),
+                )
+            })?;
+            for name in words {
+                let number = SYSCALLS
+                    .iter()
+                    .find(|(n, _)| n[4..] == *name)
+                    .map(|&(_, number)| number)
+                    .or_else(|| syscall_number(name))
+                    .ok_or_else(|| (i + 1, format!("unknown syscall \"{}\"", name)))?;
+                profile.syscalls.retain(|&(n, _)| n != number);
+                profile.syscalls.push((number, action));
+            }
+        }
+        Ok(profile)
+    }
+
+    ///Compiles the profile to a BPF program for seccomp.
+    fn compile(&self) -> Vec<libc::sock_filter> {
+        let mut program = vec![
+            //Syscall numbers depend on the architecture, so only allow the one this is for.
+            stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARCH),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_NR),
+        ];
+        //On x86_64, x32 syscalls are the same numbers with a high bit set, which would
+        //get around the list.
+        if cfg!(target_arch = "x86_64") {
+            program.push(jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1));
+            program.push(stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS));
+        }
+        for &(number, action) in &self.syscalls {
+            program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, number as u32, 0, 1));
+            program.push(stmt(BPF_RET | BPF_K, action.seccomp_ret()));
+        }
+        program.push(stmt(BPF_RET | BPF_K, self.default.seccomp_ret()));
+        program
+    }
+
+    ///Installs the profile for this process and everything it runs.
+    ///This sets PR_SET_NO_NEW_PRIVS, which installing a filter requires without CAP_SYS_ADMIN.
+    pub fn install(&self) -> io::Result<()> {
+        let program = self.compile();
+        let fprog = libc::sock_fprog {
+            len: program.len() as libc::c_ushort,
+            filter: program.as_ptr() as *mut _,
+        };
+        set_no_new_privs()?;
+        let mode = libc::SECCOMP_MODE_FILTER as libc::c_ulong;
+        if unsafe { libc::prctl(libc::PR_SET_SECCOMP, mode, &fprog as *const _, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+///Stops this process and everything it runs from gaining privileges through execve,
+///such as by running a setuid binary.
+pub fn set_no_new_privs() -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+///Parses a syscall given by number. On x86_64, numbers with the x32 bit set aren't allowed,
+///as those are all killed anyway.
+fn syscall_number(number: &str) -> Option<libc::c_long> {
+    match number.parse::<u32>() {
+        Ok(number) if number < X32_SYSCALL_BIT => Some(number.into()),
+        _ => None,
+    }
+}
+
+fn stmt(code: u16, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+//From linux/filter.h and linux/seccomp.h.
+const BPF_LD: u16 = 0x00;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_W: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGE: u16 = 0x30;
+const BPF_K: u16 = 0x00;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+///Offsets into struct seccomp_data.
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: u32 = 0;
+
+macro_rules! syscalls {
+    ($($name:ident)*) => {
+        &[$((stringify!($name), libc::$name)),*]
+    };
+}
+
+///The syscalls profiles can name, with their SYS_ prefix. This is every syscall libc has a
+///number for on both x86_64 and aarch64.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const COMMON_SYSCALLS: &[(&str, libc::c_long)] = syscalls![
+    SYS_read SYS_write SYS_close SYS_fstat SYS_lseek SYS_mmap SYS_mprotect SYS_munmap SYS_brk
+    SYS_rt_sigaction SYS_rt_sigprocmask SYS_rt_sigreturn SYS_ioctl SYS_pread64 SYS_pwrite64
+    SYS_readv SYS_writev SYS_sched_yield SYS_mremap SYS_msync SYS_mincore SYS_madvise
+    SYS_shmget SYS_shmat SYS_shmctl SYS_dup SYS_nanosleep SYS_getitimer SYS_setitimer
+    SYS_getpid SYS_socket SYS_connect SYS_accept SYS_sendto SYS_recvfrom SYS_sendmsg
+    SYS_recvmsg SYS_shutdown SYS_bind SYS_listen SYS_getsockname SYS_getpeername SYS_socketpair
+    SYS_setsockopt SYS_getsockopt SYS_clone SYS_execve SYS_exit SYS_wait4 SYS_kill SYS_uname
+    SYS_semget SYS_semop SYS_semctl SYS_shmdt SYS_msgget SYS_msgsnd SYS_msgrcv SYS_msgctl
+    SYS_fcntl SYS_flock SYS_fsync SYS_fdatasync SYS_truncate SYS_ftruncate SYS_getcwd SYS_chdir
+    SYS_fchdir SYS_fchmod SYS_fchown SYS_umask SYS_gettimeofday SYS_getrusage SYS_sysinfo
+    SYS_times SYS_ptrace SYS_getuid SYS_syslog SYS_getgid SYS_setuid SYS_setgid SYS_geteuid
+    SYS_getegid SYS_setpgid SYS_getppid SYS_setsid SYS_setreuid SYS_setregid SYS_getgroups
+    SYS_setgroups SYS_setresuid SYS_getresuid SYS_setresgid SYS_getresgid SYS_getpgid
+    SYS_setfsuid SYS_setfsgid SYS_getsid SYS_capget SYS_capset SYS_rt_sigpending
+    SYS_rt_sigtimedwait SYS_rt_sigqueueinfo SYS_rt_sigsuspend SYS_sigaltstack SYS_personality
+    SYS_statfs SYS_fstatfs SYS_getpriority SYS_setpriority SYS_sched_setparam
+    SYS_sched_getparam SYS_sched_setscheduler SYS_sched_getscheduler SYS_sched_get_priority_max
+    SYS_sched_get_priority_min SYS_sched_rr_get_interval SYS_mlock SYS_munlock SYS_mlockall
+    SYS_munlockall SYS_vhangup SYS_pivot_root SYS_prctl SYS_adjtimex SYS_chroot SYS_sync
+    SYS_acct SYS_settimeofday SYS_mount SYS_umount2 SYS_swapon SYS_swapoff SYS_reboot
+    SYS_sethostname SYS_setdomainname SYS_init_module SYS_delete_module SYS_quotactl
+    SYS_nfsservctl SYS_gettid SYS_readahead SYS_setxattr SYS_lsetxattr SYS_fsetxattr
+    SYS_getxattr SYS_lgetxattr SYS_fgetxattr SYS_listxattr SYS_llistxattr SYS_flistxattr
+    SYS_removexattr SYS_lremovexattr SYS_fremovexattr SYS_tkill SYS_futex SYS_sched_setaffinity
+    SYS_sched_getaffinity SYS_io_setup SYS_io_destroy SYS_io_getevents SYS_io_submit
+    SYS_io_cancel SYS_lookup_dcookie SYS_remap_file_pages SYS_getdents64 SYS_set_tid_address
+    SYS_restart_syscall SYS_semtimedop SYS_timer_create SYS_timer_settime SYS_timer_gettime
+    SYS_timer_getoverrun SYS_timer_delete SYS_clock_settime SYS_clock_gettime SYS_clock_getres
+    SYS_clock_nanosleep SYS_exit_group SYS_epoll_ctl SYS_tgkill SYS_mbind SYS_set_mempolicy
+    SYS_get_mempolicy SYS_mq_open SYS_mq_unlink SYS_mq_timedsend SYS_mq_timedreceive
+    SYS_mq_notify SYS_mq_getsetattr SYS_kexec_load SYS_waitid SYS_add_key SYS_request_key
+    SYS_keyctl SYS_ioprio_set SYS_ioprio_get SYS_inotify_add_watch SYS_inotify_rm_watch
+    SYS_migrate_pages SYS_openat SYS_mkdirat SYS_mknodat SYS_fchownat SYS_newfstatat
+    SYS_unlinkat SYS_linkat SYS_symlinkat SYS_readlinkat SYS_fchmodat SYS_faccessat
+    SYS_pselect6 SYS_ppoll SYS_unshare SYS_set_robust_list SYS_get_robust_list SYS_splice
+    SYS_tee SYS_vmsplice SYS_move_pages SYS_utimensat SYS_epoll_pwait SYS_timerfd_create
+    SYS_fallocate SYS_timerfd_settime SYS_timerfd_gettime SYS_accept4 SYS_signalfd4
+    SYS_eventfd2 SYS_epoll_create1 SYS_dup3 SYS_pipe2 SYS_inotify_init1 SYS_preadv SYS_pwritev
+    SYS_rt_tgsigqueueinfo SYS_perf_event_open SYS_recvmmsg SYS_fanotify_init SYS_fanotify_mark
+    SYS_prlimit64 SYS_name_to_handle_at SYS_open_by_handle_at SYS_clock_adjtime SYS_syncfs
+    SYS_sendmmsg SYS_setns SYS_getcpu SYS_process_vm_readv SYS_process_vm_writev SYS_kcmp
+    SYS_finit_module SYS_sched_setattr SYS_sched_getattr SYS_renameat2 SYS_seccomp
+    SYS_getrandom SYS_memfd_create SYS_bpf SYS_execveat SYS_userfaultfd SYS_membarrier
+    SYS_mlock2 SYS_copy_file_range SYS_preadv2 SYS_pwritev2 SYS_pkey_mprotect SYS_pkey_alloc
+    SYS_pkey_free SYS_statx SYS_pidfd_send_signal SYS_io_uring_setup SYS_io_uring_enter
+    SYS_io_uring_register SYS_open_tree SYS_move_mount SYS_fsopen SYS_fsconfig SYS_fsmount
+    SYS_fspick SYS_pidfd_open SYS_clone3 SYS_close_range SYS_openat2 SYS_pidfd_getfd
+    SYS_faccessat2 SYS_process_madvise SYS_epoll_pwait2 SYS_mount_setattr
+];
+
+///Syscalls only x86_64 has, which other architectures replaced with *at versions and the like.
+#[cfg(target_arch = "x86_64")]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = syscalls![
+    SYS_open SYS_stat SYS_lstat SYS_poll SYS_access SYS_pipe SYS_select SYS_dup2 SYS_pause
+    SYS_alarm SYS_sendfile SYS_fork SYS_vfork SYS_getdents SYS_rename SYS_mkdir SYS_rmdir
+    SYS_creat SYS_link SYS_unlink SYS_symlink SYS_readlink SYS_chmod SYS_chown SYS_lchown
+    SYS_getrlimit SYS_getpgrp SYS_utime SYS_mknod SYS_uselib SYS_ustat SYS_sysfs SYS_modify_ldt
+    SYS__sysctl SYS_arch_prctl SYS_setrlimit SYS_iopl SYS_ioperm SYS_create_module
+    SYS_get_kernel_syms SYS_query_module SYS_getpmsg SYS_putpmsg SYS_afs_syscall SYS_tuxcall
+    SYS_security SYS_time SYS_set_thread_area SYS_get_thread_area SYS_epoll_create
+    SYS_epoll_ctl_old SYS_epoll_wait_old SYS_fadvise64 SYS_epoll_wait SYS_utimes SYS_vserver
+    SYS_inotify_init SYS_futimesat SYS_renameat SYS_sync_file_range SYS_signalfd SYS_eventfd
+    SYS_kexec_file_load
+];
+#[cfg(target_arch = "aarch64")]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = &[];
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+static SYSCALLS: once_cell::sync::Lazy<Vec<(&str, libc::c_long)>> =
+    once_cell::sync::Lazy::new(|| [COMMON_SYSCALLS, ARCH_SYSCALLS].concat());
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+static SYSCALLS: once_cell::sync::Lazy<Vec<(&str, libc::c_long)>> =
+    once_cell::sync::Lazy::new(Vec::new);
+
+///Tests for parsing profiles.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = Profile::parse(
+            "# Keep debuggers out.\ndefault allow\ndeny ptrace mount\nkill kexec_load\nallow mount",
+        )
+        .unwrap();
+        assert_eq!(profile.default, Action::Allow);
+        assert_eq!(
+            profile.syscalls,
+            [
+                (libc::SYS_ptrace, Action::Deny),
+                (libc::SYS_kexec_load, Action::Kill),
+                (libc::SYS_mount, Action::Allow),
+            ]
+        );
+        assert_eq!(Profile::parse("").unwrap().default, Action::Deny);
+        assert_eq!(
+            Profile::parse("allow read\nallow frobnicate"),
+            Err((2, "unknown syscall \"frobnicate\"".to_owned()))
+        );
+        assert_eq!(
+            Profile::parse("allow newfstatat clone3 334")
+                .unwrap()
+                .syscalls,
+            [
+                (libc::SYS_newfstatat, Action::Allow),
+                (libc::SYS_clone3, Action::Allow),
+                (334, Action::Allow),
+            ]
+        );
+        assert!(Profile::parse("allow -1").is_err());
+        assert!(Profile::parse("permit read").is_err());
+        assert!(Profile::parse("default").is_err());
+    }
+}
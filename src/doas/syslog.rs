@@ -0,0 +1,112 @@
+//!This module sends messages to syslog, through the /dev/log socket.
+//!Messages are in the traditional BSD format, `<PRI>Mmm dd hh:mm:ss doas[pid]: message`,
+//!with the AUTHPRIV facility since they're about who got to run what.
+//!Control characters in messages are escaped as #ooo, in octal, as rsyslog does, so nothing in
+//!them can start what looks like another line.
+//!A message too long for one line is split over several records, each marked as continued, so
+//!that loggers with a limit on a line still get all of it.
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+///The socket the system logger listens on.
+pub const SOCKET: &str = "/dev/log";
+
+///LOG_AUTHPRIV from syslog.h, already shifted into place.
+const FACILITY_AUTHPRIV: u8 = 10 << 3;
+
+///How much of a message goes in one record, like sudo's limit on a line.
+const MAX_LINE_LEN: usize = 1024;
+
+///How important a message is, with the values syslog.h gives them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Err = 3,
+    Notice = 5,
+    Info = 6,
+}
+
+///Sends message to the logger listening on socket, over as many records as it takes.
+pub fn send(socket: &Path, severity: Severity, message: &str) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    for record in split(message) {
+        datagram.send_to(format(severity, &record).as_bytes(), socket)?;
+    }
+    Ok(())
+}
+
+fn format(severity: Severity, record: &str) -> String {
+    format!(
+        "<{}>{} doas[{}]: {}",
+        FACILITY_AUTHPRIV | severity as u8,
+        chrono::Local::now().format("%b %e %H:%M:%S"),
+        std::process::id(),
+        record
+    )
+}
+
+///Escapes message and splits it into records of at most MAX_LINE_LEN bytes, besides the marks
+///saying where one was cut. An escape is never cut in two.
+fn split(message: &str) -> Vec<String> {
+    let mut records = vec![];
+    let mut record = String::new();
+    for c in message.chars().map(escape) {
+        if record.len() + c.len() > MAX_LINE_LEN {
+            records.push(format!("{} (continued)", record));
+            record = "(continued) ".to_owned();
+        }
+        record.push_str(&c);
+    }
+    records.push(record);
+    records
+}
+
+fn escape(c: char) -> String {
+    if c.is_control() {
+        format!("#{:03o}", c as u32)
+    } else {
+        c.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_send() {
+        let dir = std::env::temp_dir().join(format!("doas-syslog-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("log");
+        let logger = UnixDatagram::bind(&socket).unwrap();
+        send(&socket, Severity::Notice, "failed auth for alice\n<85>").unwrap();
+        let mut buf = [0; 1024];
+        let read = logger.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..read]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(message.starts_with("<85>"), "{}", message);
+        assert!(
+            message.ends_with(&format!(
+                " doas[{}]: failed auth for alice#012<85>",
+                std::process::id()
+            )),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn check_split() {
+        let line = "a".repeat(MAX_LINE_LEN);
+        assert_eq!(split(&line), vec![line.clone()]);
+        let records = split(&format!("{}\n{}", &line[2..], line));
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0], format!("{} (continued)", &line[2..]));
+        assert_eq!(
+            records[1],
+            format!("(continued) #012{} (continued)", &line[16..])
+        );
+        assert_eq!(records[2], format!("(continued) {}", &line[..16]));
+        assert_eq!(records.concat().matches('a').count(), 2 * MAX_LINE_LEN - 2);
+    }
+}
@@ -16,6 +16,8 @@ pub static SHOULD_LOG: OnceCell<bool> = OnceCell::new();
 #[structopt(setting = structopt::clap::AppSettings::TrailingVarArg)]
 pub struct Options {
     ///Use config file at this path, then exit.
+    ///The file is read as the current user, doesn't have to belong to root, and is checked
+    ///without the drop-ins in /etc/doas.d.
     ///If command is supplied, doas will also perform command matching.
     ///In the latter case either 'permit', 'permit nopass', or 'deny' will be printed on standard
     ///output, depending on the command matching results. No command is executed.
@@ -27,6 +29,21 @@ pub struct Options {
     #[structopt(short = "L", long = "clear-persisted-auth")]
     clear_persisted_auth: bool,
 
+    ///List the rules that apply to the current user, then exit.
+    ///Aliases in the config are shown expanded. No command is executed.
+    #[structopt(short = "l", long = "list")]
+    list: bool,
+
+    ///Run the target user's shell as a login shell, in their home with a fresh environment.
+    ///The config has to permit running the shell as a command.
+    #[structopt(short = "i", long = "login", conflicts_with_all = &["command", "chdir", "shell"])]
+    login: bool,
+
+    ///Run the command in this directory, rather than the current one or the rule's cwd.
+    ///Rules with nochdir don't allow this.
+    #[structopt(parse(from_os_str), short = "D", long = "chdir")]
+    chdir: Option<std::path::PathBuf>,
+
     ///Non interactive mode, fail if doas would prompt for password.
     #[structopt(short = "n", long = "non-interactive-mode")]
     non_interactive_mode: bool,
@@ -36,13 +53,14 @@ pub struct Options {
     shell: Option<std::path::PathBuf>,
 
     ///Execute the command as supplied user. The default is root.
-    #[structopt(short = "u", long = "user", default_value = "root")]
-    user: String,
+    ///Rules granting capabilities run the command as the current user unless this is given.
+    #[structopt(short = "u", long = "user")]
+    user: Option<String>,
 
     ///The command to run under doas.
     #[structopt(
         min_values = 1,
-        required_unless_one = &["shell","clear-persisted-auth", "config-file"],
+        required_unless_one = &["shell","clear-persisted-auth", "config-file", "list", "login"],
     )]
     command: Vec<String>,
 }
@@ -0,0 +1,77 @@
+//! This module holds the aliases defined in a config, such as `alias ADMINS = { alice :wheel }`.
+//! Aliases are expanded at parse time, so the rules coming out of the parser never contain them.
+//! Alias names are written in upper case, which is how the parser tells them apart from the
+//! users, groups and commands they stand in for. A user or command whose name is in upper case
+//! has to be quoted, as in `permit "BOB"`, or it's taken as an alias.
+//! One table of aliases is shared by every file of a config, so an alias defined in
+//! /etc/doas.conf can be used in the files it includes and in drop-ins.
+use super::ParserError;
+use std::collections::HashMap;
+
+///All the aliases defined in a config, by name.
+#[derive(Debug, Default)]
+pub struct Aliases<'a> {
+    definitions: HashMap<&'a str, Vec<&'a str>>,
+}
+
+impl<'a> Aliases<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Defines the alias name as standing for the given items.
+    ///The items may refer to other aliases, which get resolved when the alias is used.
+    pub fn define(&mut self, name: &'a str, items: Vec<&'a str>) -> Result<(), ParserError<'a>> {
+        if !is_alias_name(name) {
+            return Err(ParserError::InvalidAliasName(name));
+        }
+        if self.definitions.contains_key(name) {
+            return Err(ParserError::DuplicateAlias(name));
+        }
+        self.definitions.insert(name, items);
+        Ok(())
+    }
+
+    ///Replaces every alias in items with what it stands for, recursively.
+    ///Errors if an alias isn't defined or ends up referring to itself.
+    pub fn expand(&self, items: &[&'a str]) -> Result<Vec<&'a str>, ParserError<'a>> {
+        self.expand_with_stack(items, &mut vec![])
+    }
+
+    fn expand_with_stack(
+        &self,
+        items: &[&'a str],
+        stack: &mut Vec<&'a str>,
+    ) -> Result<Vec<&'a str>, ParserError<'a>> {
+        let mut expanded = vec![];
+        for &item in items {
+            if !is_alias_name(item) {
+                expanded.push(item);
+                continue;
+            }
+            if stack.contains(&item) {
+                return Err(ParserError::RecursiveAlias(item));
+            }
+            let definition = self
+                .definitions
+                .get(item)
+                .ok_or(ParserError::UndefinedAlias(item))?;
+            stack.push(item);
+            expanded.extend(self.expand_with_stack(definition, stack)?);
+            stack.pop();
+        }
+        Ok(expanded)
+    }
+}
+
+///Alias names start with an upper case letter, and contain only upper case letters,
+///digits and underscores.
+fn is_alias_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_uppercase() => {
+            chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        }
+        _ => false,
+    }
+}
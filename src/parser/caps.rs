@@ -0,0 +1,83 @@
+//! This module defines the Linux capabilities a rule can grant with `caps { cap_net_raw }`.
+//! A rule with caps runs its command with only those capabilities, rather than as full root.
+//! The command runs as the caller, or the user given with -u, and it can only be root when the
+//! rule says `as root`, since root still owns root's files whatever its capabilities.
+use super::ParserError;
+
+///The capabilities, in the order of their numbers in linux/capability.h.
+const CAPABILITIES: &[&str] = &[
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+///A Linux capability.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Capability(u32);
+
+impl Capability {
+    ///The number the kernel knows the capability by.
+    pub fn number(self) -> u32 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", CAPABILITIES[self.0 as usize])
+    }
+}
+
+///Looks up capabilities by name, such as cap_net_raw. Case and the cap_ prefix don't matter.
+pub(super) fn capabilities(names: Vec<&str>) -> Result<Vec<Capability>, ParserError<'_>> {
+    names
+        .into_iter()
+        .map(|name| {
+            let lower = name.to_ascii_lowercase();
+            let lower = lower.strip_prefix("cap_").unwrap_or(&lower);
+            CAPABILITIES
+                .iter()
+                .position(|cap| cap[4..] == *lower)
+                .map(|number| Capability(number as u32))
+                .ok_or(ParserError::UnknownCapability(name))
+        })
+        .collect()
+}
@@ -1,5 +1,5 @@
-///! This module takes given contents and spits out Tokens that are more easily digested
-///! By programs.
+//! This module takes given contents and spits out Tokens that are more easily digested
+//! By programs.
 use nom::bytes::complete::tag;
 use nom::bytes::complete::take_till;
 use nom::bytes::complete::take_until;
@@ -15,30 +15,67 @@ mod lexer_tests;
 ///lexer errors exist due to ambiguities (is a token a part of setenv? inside quotes?)
 ///when something is missing.
 #[allow(dead_code)]
-pub fn get_tokens(data: &str) -> Result<Vec<Token>, LexerError<&str>> {
+pub fn get_tokens(data: &str) -> Result<Vec<Token<'_>>, LexerError<&str>> {
+    get_tokens_and_lines(data).map(|(tokens, _)| tokens)
+}
+
+///Same as get_tokens, but also returns the line number (starting at 1) each token is on.
+///The two vectors are the same length.
+///Braces delimit lists, except in the args of a rule, which run to the end of the line and
+///can have braces of their own, as in `args -exec {} ;`.
+pub fn get_tokens_and_lines(data: &str) -> Result<(Vec<Token<'_>>, Vec<usize>), LexerError<&str>> {
+    let original = data;
     //This will be updated to hold the remaining data we have yet to parse.
     let mut data = data.trim();
+    let mut line = 1 + original[..original.len() - data.trim_start().len()]
+        .matches('\n')
+        .count();
     let mut tokens = Vec::new();
+    let mut lines = Vec::new();
+    let mut in_args = false;
     while let Ok((remaining, token)) =
-        get_next_token(data).map_err(|_| LexerError::CouldntGetNextToken::<&str>)
+        get_next_token(data, !in_args).map_err(|_| LexerError::CouldntGetNextToken::<&str>)
     {
-        data = remaining;
+        in_args = match token {
+            Token::Args => true,
+            Token::Eol => false,
+            _ => in_args,
+        };
         tokens.push(token);
+        lines.push(line);
+        line += data[..data.len() - remaining.len()].matches('\n').count();
+        data = remaining;
     }
     tokens.push(Token::Eol);
-    Ok(tokens)
+    lines.push(line);
+    Ok((tokens, lines))
 }
 
 ///This takes the data, returns the next token along with the remaining data.
-fn get_next_token(data: &str) -> nom::IResult<&str, Token, LexerError<&str>> {
+///Unless braces is set, braces are taken as part of words rather than tokens of their own.
+fn get_next_token(data: &str, braces: bool) -> nom::IResult<&str, Token<'_>, LexerError<&str>> {
+    //Newlines and braces are tokens of their own, so only skip the blanks in front of them.
+    let data = data.trim_start_matches([' ', '\t']);
     if let Some(data) = data.strip_prefix('\n') {
         return Ok((data, Token::from("\n")));
     }
-    let (remaining, word) = get_next_word(" \t\n")(data)?;
+    if braces {
+        if let Some(data) = data.strip_prefix('{') {
+            return Ok((data, Token::OpenBrace));
+        }
+        if let Some(data) = data.strip_prefix('}') {
+            return Ok((data, Token::CloseBrace));
+        }
+    }
+    let separators = if braces { " \t\n{}" } else { " \t\n" };
+    let (remaining, word) = get_next_word(separators)(data)?;
     if word == "#" {
         //if it's a comment just ignore it, and go to the next relevant thing.
         let (remaining, _) = take_until("\n")(remaining)?;
-        return get_next_token(remaining);
+        return get_next_token(remaining, braces);
+    }
+    if !braces && (word == "{" || word == "}") {
+        return Ok((remaining, Token::Ident(word)));
     }
     if word == "setenv" {
         return parse_set_env(remaining);
@@ -51,7 +88,7 @@ fn get_next_token(data: &str) -> nom::IResult<&str, Token, LexerError<&str>> {
 ///This parses the set enviorment seperately from get_next_token
 ///due to the added complexity of handling setenv.
 ///It returns a Token::SetEnv or a lexer error.
-fn parse_set_env(data: &str) -> nom::IResult<&str, Token, LexerError<&str>> {
+fn parse_set_env(data: &str) -> nom::IResult<&str, Token<'_>, LexerError<&str>> {
     let (remaining, _) = take_till(|c| c == '{')(data)?;
     let (remaining, _) = tag::<_, _, ()>("{")(remaining) //take the first brace out.
         .map_err(|_| nom::Err::Failure(LexerError::NoOrUnmatchedBracket))?;
@@ -65,7 +102,7 @@ fn parse_set_env(data: &str) -> nom::IResult<&str, Token, LexerError<&str>> {
     } else {
         tokens.len()
     };
-    let map = (&tokens[..num_to_take])
+    let map = tokens[..num_to_take]
         .chunks(2)
         .map(|a| {
             if let [a, b] = a {
@@ -127,25 +164,98 @@ pub enum Token<'a> {
     As,
     Cmd,
     Args,
+    Alias,
+    Equals,
+    Include,
+    Digest,
+    Path,
+    Export,
+    AllowDangerousEnv,
+    Cwd,
+    NoChdir,
+    Umask,
+    Rlimit,
+    Nice,
+    Timeout,
+    Caps,
+    NoNewPrivs,
+    Seccomp,
+    PrivateTmp,
+    ReadonlyRoot,
+    NoNetwork,
+    Chroot,
+    UsePty,
+    Record,
+    AuditLog,
+    Logger,
     Eol,
+    OpenBrace,
+    CloseBrace,
     Ident(&'a str),
     SetEnv(HashMap<&'a str, &'a str>),
 }
 
+impl<'a> Token<'a> {
+    ///Returns the word the token was lexed from, for anything but line ends, braces and setenv.
+    ///Where a command or its args are expected, keywords are just words, so a rule can still
+    ///allow `cmd timeout` or `args nice 5`.
+    pub fn word(&self) -> Option<&'a str> {
+        match self {
+            Self::Ident(identifier) => Some(identifier),
+            Self::Eol | Self::OpenBrace | Self::CloseBrace | Self::SetEnv(_) => None,
+            keyword => keyword.keyword(),
+        }
+    }
+
+    ///Returns the keyword the token stands for, if it's one.
+    fn keyword(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Permit => "permit",
+            Self::Deny => "deny",
+            Self::Persist => "persist",
+            Self::NoPass => "nopass",
+            Self::KeepEnv => "keepenv",
+            Self::As => "as",
+            Self::Cmd => "cmd",
+            Self::Args => "args",
+            Self::Alias => "alias",
+            Self::Equals => "=",
+            Self::Include => "include",
+            Self::Digest => "digest",
+            Self::Path => "path",
+            Self::Export => "export",
+            Self::AllowDangerousEnv => "allow_dangerous_env",
+            Self::Cwd => "cwd",
+            Self::NoChdir => "nochdir",
+            Self::Umask => "umask",
+            Self::Rlimit => "rlimit",
+            Self::Nice => "nice",
+            Self::Timeout => "timeout",
+            Self::Caps => "caps",
+            Self::NoNewPrivs => "nonewprivs",
+            Self::Seccomp => "seccomp",
+            Self::PrivateTmp => "private-tmp",
+            Self::ReadonlyRoot => "readonly-root",
+            Self::NoNetwork => "no-network",
+            Self::Chroot => "chroot",
+            Self::UsePty => "usepty",
+            Self::Record => "record",
+            Self::AuditLog => "auditlog",
+            Self::Logger => "logger",
+            _ => return None,
+        })
+    }
+}
+
 impl<'a> std::fmt::Display for Token<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Permit => write!(f, "permit"),
-            Self::Deny => write!(f, "deny"),
-            Self::Persist => write!(f, "persist"),
-            Self::NoPass => write!(f, "nopass"),
-            Self::KeepEnv => write!(f, "keepenv"),
-            Self::As => write!(f, "as"),
-            Self::Cmd => write!(f, "cmd"),
-            Self::Args => write!(f, "args"),
             Self::Eol => write!(f, "End Of Line"),
+            Self::OpenBrace => write!(f, "{{"),
+            Self::CloseBrace => write!(f, "}}"),
             Self::Ident(identifier) => write!(f, "{}", identifier),
             Self::SetEnv(map) => write!(f, "setenv {{{:?}}}", map),
+            keyword => write!(f, "{}", keyword.keyword().unwrap_or_default()),
         }
     }
 }
@@ -162,6 +272,32 @@ impl<'a> From<&'a str> for Token<'a> {
             "as" => Self::As,
             "cmd" => Self::Cmd,
             "args" => Self::Args,
+            "alias" => Self::Alias,
+            "=" => Self::Equals,
+            "include" => Self::Include,
+            "digest" => Self::Digest,
+            "path" => Self::Path,
+            "export" => Self::Export,
+            "allow_dangerous_env" => Self::AllowDangerousEnv,
+            "cwd" => Self::Cwd,
+            "nochdir" => Self::NoChdir,
+            "umask" => Self::Umask,
+            "rlimit" => Self::Rlimit,
+            "nice" => Self::Nice,
+            "timeout" => Self::Timeout,
+            "caps" => Self::Caps,
+            "nonewprivs" => Self::NoNewPrivs,
+            "seccomp" => Self::Seccomp,
+            "private-tmp" => Self::PrivateTmp,
+            "readonly-root" => Self::ReadonlyRoot,
+            "no-network" => Self::NoNetwork,
+            "chroot" => Self::Chroot,
+            "usepty" => Self::UsePty,
+            "record" => Self::Record,
+            "auditlog" => Self::AuditLog,
+            "logger" => Self::Logger,
+            "{" => Self::OpenBrace,
+            "}" => Self::CloseBrace,
             c => Self::Ident(c),
         }
     }
@@ -76,3 +76,49 @@ fn test_full_line() {
         ])
     )
 }
+
+#[test]
+fn test_token_lines() {
+    assert_eq!(
+        get_tokens_and_lines("\n# comment\npermit bender\n\n  deny\tjohn # trailing\n"),
+        Ok((
+            vec![
+                Token::from("\n"),
+                Token::from("permit"),
+                Token::from("bender"),
+                Token::from("\n"),
+                Token::from("\n"),
+                Token::from("deny"),
+                Token::from("john"),
+                Token::from("\n"),
+            ],
+            vec![2, 3, 3, 3, 4, 5, 5, 5]
+        ))
+    )
+}
+
+#[test]
+fn test_braces_in_args() {
+    assert_eq!(
+        get_tokens("permit {alice} cmd find args -exec {} ; {\n deny{bob}"),
+        Ok(vec![
+            Token::from("permit"),
+            Token::OpenBrace,
+            Token::from("alice"),
+            Token::CloseBrace,
+            Token::from("cmd"),
+            Token::from("find"),
+            Token::from("args"),
+            Token::from("-exec"),
+            Token::from("{}"),
+            Token::from(";"),
+            Token::Ident("{"),
+            Token::from("\n"),
+            Token::from("deny"),
+            Token::OpenBrace,
+            Token::from("bob"),
+            Token::CloseBrace,
+            Token::from("\n"),
+        ])
+    )
+}
@@ -0,0 +1,138 @@
+//! This module defines the resource limits and nice level a rule can run its command with,
+//! written `rlimit { nofile 4096 as 2G cpu 600 }` and `nice 10`.
+//! Each limit sets both the soft and hard limit, so the command can't raise it back.
+//! A rule can also give a wall clock timeout, such as `timeout 15m`, after which the command
+//! is killed.
+use super::ParserError;
+
+use std::time::Duration;
+
+///A resource setrlimit can limit, named as in the config.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Resource {
+    ///Address space, in bytes.
+    As,
+    ///Core file size, in bytes.
+    Core,
+    ///CPU time, in seconds.
+    Cpu,
+    ///Data segment size, in bytes.
+    Data,
+    ///Size of files the command creates, in bytes.
+    Fsize,
+    ///Locked memory, in bytes.
+    Memlock,
+    ///Open file descriptors.
+    Nofile,
+    ///Processes of the target user.
+    Nproc,
+    ///Stack size, in bytes.
+    Stack,
+}
+
+const RESOURCES: &[(&str, Resource)] = &[
+    ("as", Resource::As),
+    ("core", Resource::Core),
+    ("cpu", Resource::Cpu),
+    ("data", Resource::Data),
+    ("fsize", Resource::Fsize),
+    ("memlock", Resource::Memlock),
+    ("nofile", Resource::Nofile),
+    ("nproc", Resource::Nproc),
+    ("stack", Resource::Stack),
+];
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, _) = RESOURCES.iter().find(|(_, r)| r == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+///A limit on a resource. A limit of None is unlimited.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Rlimit {
+    pub resource: Resource,
+    pub limit: Option<u64>,
+}
+
+impl std::fmt::Display for Rlimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.limit {
+            Some(limit) => write!(f, "{} {}", self.resource, limit),
+            None => write!(f, "{} infinity", self.resource),
+        }
+    }
+}
+
+///Parses a limit such as `nofile 4096`. Values can have a K, M, G or T suffix (powers of 1024),
+///or be `infinity` for no limit.
+pub(super) fn rlimit<'a>(name: &'a str, value: &'a str) -> Result<Rlimit, ParserError<'a>> {
+    let resource = RESOURCES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(_, resource)| resource)
+        .ok_or(ParserError::UnknownRlimit(name))?;
+    if value == "infinity" {
+        return Ok(Rlimit {
+            resource,
+            limit: None,
+        });
+    }
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, 'K')) => (&value[..i], 1 << 10),
+        Some((i, 'M')) => (&value[..i], 1 << 20),
+        Some((i, 'G')) => (&value[..i], 1 << 30),
+        Some((i, 'T')) => (&value[..i], 1 << 40),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|limit| limit.checked_mul(multiplier))
+        .map(|limit| Rlimit {
+            resource,
+            limit: Some(limit),
+        })
+        .ok_or(ParserError::InvalidRlimit(value))
+}
+
+///The units a timeout can be given in, and their length in seconds, longest first.
+const TIMEOUT_UNITS: &[(char, u64)] = &[('d', 86400), ('h', 3600), ('m', 60), ('s', 1)];
+
+///Parses a timeout such as `90`, `90s`, `15m`, `2h` or `1d`. It can't be zero.
+pub(super) fn timeout(value: &str) -> Result<Duration, ParserError<'_>> {
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, unit)) => match TIMEOUT_UNITS.iter().find(|(u, _)| *u == unit) {
+            Some((_, multiplier)) => (&value[..i], *multiplier),
+            None => (value, 1),
+        },
+        None => (value, 1),
+    };
+    match digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+    {
+        Some(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+        _ => Err(ParserError::InvalidTimeout(value)),
+    }
+}
+
+///Writes the timeout in the longest unit it's a whole number of, such as `15m`.
+pub fn display_timeout(timeout: Duration) -> String {
+    let secs = timeout.as_secs();
+    let (unit, len) = TIMEOUT_UNITS
+        .iter()
+        .find(|(_, len)| secs % len == 0)
+        .unwrap_or(&('s', 1));
+    format!("{}{}", secs / len, unit)
+}
+
+///Parses a nice level, from -20 (highest priority) to 19 (lowest).
+pub(super) fn nice(value: &str) -> Result<i32, ParserError<'_>> {
+    match value.parse() {
+        Ok(nice) if (-20..=19).contains(&nice) => Ok(nice),
+        _ => Err(ParserError::InvalidNice(value)),
+    }
+}
@@ -8,36 +8,143 @@ use parser_err::ParserError;
 pub mod rules;
 use rules::Rule;
 
+pub mod aliases;
+use aliases::Aliases;
+
+pub mod pattern;
+
+pub mod settings;
+
+pub mod limits;
+
+pub mod caps;
+use settings::Setting;
+
 #[cfg(test)]
 mod tests;
 
 mod lexer;
 
+///An item of the config, in the order it was written.
+//Entries only live until the config is loaded, so rules aren't worth boxing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Entry {
+    Rule(Rule),
+    ///Another config file to read in at this point, as written in the config.
+    Include(String),
+    Setting(Setting),
+}
+
 ///Parse the rules in contents.
+///Alias definitions are collected along the way, and expanded into the rules once the whole
+///config has been read, so an alias can be used before the line that defines it.
+///Anything in the config that isn't a rule is left out.
 #[allow(dead_code)]
 pub fn parse_rules(contents: &str) -> Vec<Result<Rule, ParserError<'_>>> {
-    let tokens = lexer::get_tokens(contents).unwrap_or_else(|e| panic!("Got err {:?}", e));
-    let mut tokens = tokens.into_iter().peekable();
+    parse_config(contents)
+        .into_iter()
+        .filter_map(|(_, entry)| match entry {
+            Ok(Entry::Rule(rule)) => Some(Ok(rule)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .collect()
+}
+
+///Parse every entry in contents, along with the line each one starts on.
+///Only aliases defined in contents can be used; for a config spread over several files, see
+///read_entries and expand_entries.
+pub fn parse_config(contents: &str) -> Vec<(usize, Result<Entry, ParserError<'_>>)> {
+    let mut aliases = Aliases::new();
+    let entries = read_entries(contents, &mut aliases);
+    expand_entries(entries, &aliases)
+}
+
+///Returns the files contents includes, in the order they're included, as written.
+pub fn includes(contents: &str) -> Vec<String> {
+    read_entries(contents, &mut Aliases::new())
+        .into_iter()
+        .filter_map(|(_, entry)| match entry {
+            Ok(PendingEntry::Include(path)) => Some(path),
+            _ => None,
+        })
+        .collect()
+}
+
+///Reads every entry in contents, along with the line each one starts on, adding the aliases
+///it defines to aliases. The rules may use aliases that haven't been defined yet, so they're
+///only expanded by expand_entries, once every alias is known.
+pub fn read_entries<'a>(
+    contents: &'a str,
+    aliases: &mut Aliases<'a>,
+) -> Vec<(usize, Result<PendingEntry<'a>, ParserError<'a>>)> {
+    let (tokens, lines) =
+        lexer::get_tokens_and_lines(contents).unwrap_or_else(|e| panic!("Got err {:?}", e));
+    let mut tokens = Tokens::new(tokens, lines);
     let mut rules = vec![];
     'main: loop {
         let rule = rules::RuleBuilder::new();
-        let rule = match tokens.next() {
+        let next_token = tokens.next();
+        let rules = &mut Lines::new(&mut rules, tokens.line);
+        let rule = match next_token {
             Some(lexer::Token::Permit) => rule.permit(),
             Some(lexer::Token::Deny) => rule.deny(),
+            Some(lexer::Token::Alias) => {
+                if let Err(e) = get_alias(aliases, &mut tokens) {
+                    rules.push(Err(e));
+                    go_until_next_rule(&mut tokens);
+                }
+                continue;
+            }
+            Some(lexer::Token::Include) => {
+                match tokens.next() {
+                    Some(lexer::Token::Ident(path)) => {
+                        rules.push(Ok(PendingEntry::Include(rules::escaped_string(path))))
+                    }
+                    Some(token) => {
+                        rules.push(Err(ParserError::ExpectedIncludePathGot(token)));
+                        go_until_next_rule(&mut tokens);
+                    }
+                    None => rules.push(Err(ParserError::ExpectedIncludePathGot(lexer::Token::Eol))),
+                }
+                continue;
+            }
+            Some(
+                setting @ lexer::Token::Path
+                | setting @ lexer::Token::Export
+                | setting @ lexer::Token::Umask
+                | setting @ lexer::Token::AuditLog
+                | setting @ lexer::Token::Logger,
+            ) => {
+                match get_ident_or_list(&mut tokens)
+                    .and_then(|values| settings::setting(&setting, values))
+                {
+                    Ok(setting) => rules.push(Ok(PendingEntry::Setting(setting))),
+                    Err(e) => {
+                        rules.push(Err(e));
+                        go_until_next_rule(&mut tokens);
+                    }
+                }
+                continue;
+            }
+            Some(lexer::Token::UsePty) => {
+                match tokens.next() {
+                    Some(lexer::Token::Eol) | None => {
+                        rules.push(Ok(PendingEntry::Setting(Setting::UsePty)))
+                    }
+                    Some(token) => {
+                        rules.push(Err(ParserError::ExpectedEolGot(token)));
+                        go_until_next_rule(&mut tokens);
+                    }
+                }
+                continue;
+            }
             Some(lexer::Token::Eol) => continue,
             Some(token) => {
                 rules.push(Err(ParserError::ExpectedRuleGot(token)));
-                loop {
-                    let next_item = tokens.peek();
-                    if next_item == Some(&lexer::Token::Permit)
-                        || next_item == Some(&lexer::Token::Deny)
-                        || next_item == None
-                    {
-                        continue 'main;
-                    } else {
-                        tokens.next();
-                    }
-                }
+                go_until_next_rule(&mut tokens);
+                continue;
             }
             None => break 'main,
         };
@@ -53,20 +160,31 @@ pub fn parse_rules(contents: &str) -> Vec<Result<Rule, ParserError<'_>>> {
 
         loop {
             match tokens.next() {
-                Some(lexer::Token::As) => match tokens.next() {
-                    Some(lexer::Token::Ident(target)) => rule = rule.target(target),
-                    Some(token) => {
+                Some(lexer::Token::As) => match get_ident_or_list(&mut tokens) {
+                    Ok(targets) => rule = targets.into_iter().fold(rule, |rule, t| rule.target(t)),
+                    Err(ParserError::ExpectedIdentOrListGot(token)) => {
                         rules.push(Err(ParserError::ExpectedTargetGot(token)));
+                        go_until_next_rule(&mut tokens);
+                        continue 'main;
+                    }
+                    Err(e) => {
+                        rules.push(Err(e));
+                        go_until_next_rule(&mut tokens);
                         continue 'main;
                     }
-                    None => rules.push(Err(ParserError::ExpectedTargetGot(lexer::Token::Eol))),
                 },
                 Some(lexer::Token::Cmd) => {
-                    rules.push(get_cmd_and_args(rule, &mut tokens));
+                    match get_cmd_and_args(rule, &mut tokens) {
+                        Ok(rule) => rules.push(Ok(PendingEntry::Rule(rule))),
+                        Err(e) => {
+                            rules.push(Err(e));
+                            go_until_next_rule(&mut tokens);
+                        }
+                    }
                     break;
                 }
                 Some(lexer::Token::Eol) => {
-                    rules.push(rule.build());
+                    rules.push(Ok(PendingEntry::Rule(rule)));
                     break;
                 }
                 Some(token) => {
@@ -81,40 +199,153 @@ pub fn parse_rules(contents: &str) -> Vec<Result<Rule, ParserError<'_>>> {
     rules
 }
 
+///Expands the aliases in entries read by read_entries, and builds their rules.
+pub fn expand_entries<'a>(
+    entries: Vec<(usize, Result<PendingEntry<'a>, ParserError<'a>>)>,
+    aliases: &Aliases<'a>,
+) -> Vec<(usize, Result<Entry, ParserError<'a>>)> {
+    entries
+        .into_iter()
+        .map(|(line, entry)| {
+            let entry = entry.and_then(|entry| match entry {
+                PendingEntry::Rule(rule) => Ok(Entry::Rule(rule.expand_aliases(aliases)?.build()?)),
+                PendingEntry::Include(path) => Ok(Entry::Include(path)),
+                PendingEntry::Setting(setting) => Ok(Entry::Setting(setting)),
+            });
+            (line, entry)
+        })
+        .collect()
+}
+
+///An entry which has been read, but which may still contain aliases.
+#[allow(clippy::large_enum_variant)]
+pub enum PendingEntry<'a> {
+    Rule(rules::RuleBuilder<'a>),
+    Include(String),
+    Setting(Setting),
+}
+
+///The tokens of a config, keeping track of the line the last token taken was on.
+struct Tokens<'a> {
+    tokens: std::iter::Peekable<
+        std::iter::Zip<std::vec::IntoIter<lexer::Token<'a>>, std::vec::IntoIter<usize>>,
+    >,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(tokens: Vec<lexer::Token<'a>>, lines: Vec<usize>) -> Self {
+        Self {
+            tokens: tokens.into_iter().zip(lines).peekable(),
+            line: 1,
+        }
+    }
+
+    fn peek(&mut self) -> Option<&lexer::Token<'a>> {
+        self.tokens.peek().map(|(token, _)| token)
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = lexer::Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (token, line) = self.tokens.next()?;
+        self.line = line;
+        Some(token)
+    }
+}
+
+///Pushes entries onto a list, tagging each with the line the current entry started on.
+struct Lines<'a, 'b> {
+    entries: &'b mut Vec<(usize, Result<PendingEntry<'a>, ParserError<'a>>)>,
+    line: usize,
+}
+
+impl<'a, 'b> Lines<'a, 'b> {
+    fn new(
+        entries: &'b mut Vec<(usize, Result<PendingEntry<'a>, ParserError<'a>>)>,
+        line: usize,
+    ) -> Self {
+        Self { entries, line }
+    }
+
+    fn push(&mut self, entry: Result<PendingEntry<'a>, ParserError<'a>>) {
+        self.entries.push((self.line, entry));
+    }
+}
+
 ///This gets commands and args inside of the iterator. The last part of a rule.
+///Keywords are taken as plain words here, see lexer::Token::word.
 pub fn get_cmd_and_args<'a, T: Iterator<Item = lexer::Token<'a>>>(
     mut builder: rules::RuleBuilder<'a>,
     tokens: &mut T,
-) -> Result<rules::Rule, ParserError<'a>> {
-    match tokens.next() {
-        Some(lexer::Token::Ident(cmd_name)) => {
-            builder = builder.with_cmd(cmd_name);
-            match tokens.next() {
-                Some(lexer::Token::Args) => {
-                    builder = builder.with_cmd(cmd_name);
-                    let mut args = vec![];
-                    for i in tokens {
-                        if let lexer::Token::Ident(arg) = i {
-                            args.push(arg);
-                        } else if lexer::Token::Eol == i {
-                            break;
-                        }
+) -> Result<rules::RuleBuilder<'a>, ParserError<'a>> {
+    let cmds = match get_words_or_list(tokens, lexer::Token::word) {
+        Ok(cmds) => cmds,
+        Err(ParserError::ExpectedIdentOrListGot(token)) => {
+            return Err(ParserError::ExpectedCmdNameGot(token))
+        }
+        Err(e) => return Err(e),
+    };
+    builder = cmds
+        .into_iter()
+        .fold(builder, |builder, cmd| builder.with_cmd(cmd));
+    loop {
+        match tokens.next() {
+            Some(lexer::Token::Digest) => match tokens.next() {
+                Some(lexer::Token::Ident(digest)) => builder = builder.digest(digest),
+                Some(token) => return Err(ParserError::ExpectedDigestGot(token)),
+                None => return Err(ParserError::ExpectedDigestGot(lexer::Token::Eol)),
+            },
+            Some(lexer::Token::Args) => {
+                let mut args = vec![];
+                loop {
+                    match tokens.next() {
+                        Some(lexer::Token::Eol) | None => return Ok(builder.with_cmd_args(args)),
+                        Some(token) => match token.word() {
+                            Some(arg) => args.push(arg),
+                            None => return Err(ParserError::ExpectedArgGot(token)),
+                        },
                     }
-                    builder = builder.with_cmd_args(args);
-                    builder.build()
                 }
-                Some(lexer::Token::Eol) => builder.build(),
-                Some(token) => panic!("expected args token, found token {:?}", token),
-                None => builder.build(),
             }
+            Some(lexer::Token::Eol) | None => return Ok(builder),
+            Some(token) => return Err(ParserError::ExpectedArgsGot(token)),
         }
-        Some(token) => Err(ParserError::ExpectedCmdNameGot(token)),
-        None => Err(ParserError::ExpectedCmdNameGot(lexer::Token::Eol)),
     }
 }
+
+///Parses the rest of an alias definition, `NAME = value` or `NAME = { values }`,
+///and adds it to aliases.
+fn get_alias<'a, T: Iterator<Item = lexer::Token<'a>>>(
+    aliases: &mut Aliases<'a>,
+    tokens: &mut T,
+) -> Result<(), ParserError<'a>> {
+    let name = match tokens.next() {
+        Some(lexer::Token::Ident(name)) => name,
+        Some(token) => return Err(ParserError::ExpectedAliasNameGot(token)),
+        None => return Err(ParserError::ExpectedAliasNameGot(lexer::Token::Eol)),
+    };
+    match tokens.next() {
+        Some(lexer::Token::Equals) => (),
+        Some(token) => return Err(ParserError::ExpectedEqualsGot(token)),
+        None => return Err(ParserError::ExpectedEqualsGot(lexer::Token::Eol)),
+    }
+    let items = match get_ident_or_list(tokens) {
+        Ok(items) => items,
+        Err(ParserError::ExpectedIdentOrListGot(token)) => {
+            return Err(ParserError::ExpectedAliasValueGot(token))
+        }
+        Err(e) => return Err(e),
+    };
+    aliases.define(name, items)
+}
+
 ///This takes a iterator, and until it finds a identifier it keeps applying
 ///the given options to the rule builder.
-///Once it finds the identifier, set that as the identity_name and return.
+///Once it finds the identifier (or a brace delimited list of them),
+///set that as the identity_name and return.
 pub fn get_options_and_identity<'a, T: Iterator<Item = lexer::Token<'a>>>(
     mut builder: rules::RuleBuilder<'a>,
     tokens: &mut T,
@@ -125,29 +356,198 @@ pub fn get_options_and_identity<'a, T: Iterator<Item = lexer::Token<'a>>>(
             Some(lexer::Token::Persist) => builder = builder.persist(),
             Some(lexer::Token::KeepEnv) => builder = builder.keep_env(),
             Some(lexer::Token::SetEnv(m)) => builder = builder.set_env(m),
+            Some(lexer::Token::Path) => {
+                builder = builder.path(settings::search_path(get_ident_or_list(tokens)?)?)
+            }
+            Some(lexer::Token::NoChdir) => builder = builder.no_chdir(),
+            Some(lexer::Token::Rlimit) => {
+                for rlimit in get_rlimits(tokens)? {
+                    builder = builder.rlimit(rlimit);
+                }
+            }
+            Some(lexer::Token::NoNewPrivs) => builder = builder.no_new_privs(),
+            Some(lexer::Token::UsePty) => builder = builder.use_pty(),
+            Some(lexer::Token::Record) => builder = builder.record(),
+            Some(lexer::Token::Chroot) => match tokens.next() {
+                Some(lexer::Token::Ident(dir)) => builder = builder.chroot(dir),
+                Some(token) => return Err(ParserError::ExpectedChrootGot(token)),
+                None => return Err(ParserError::ExpectedChrootGot(lexer::Token::Eol)),
+            },
+            Some(lexer::Token::PrivateTmp) => builder = builder.private_tmp(),
+            Some(lexer::Token::ReadonlyRoot) => builder = builder.readonly_root(),
+            Some(lexer::Token::NoNetwork) => builder = builder.no_network(),
+            Some(lexer::Token::Seccomp) => match tokens.next() {
+                Some(lexer::Token::Ident(profile)) => builder = builder.seccomp(profile),
+                Some(token) => return Err(ParserError::ExpectedSeccompGot(token)),
+                None => return Err(ParserError::ExpectedSeccompGot(lexer::Token::Eol)),
+            },
+            Some(lexer::Token::Caps) => {
+                builder = builder.caps(caps::capabilities(get_ident_or_list(tokens)?)?)
+            }
+            Some(lexer::Token::Timeout) => match tokens.next() {
+                Some(lexer::Token::Ident(timeout)) => {
+                    builder = builder.timeout(limits::timeout(timeout)?)
+                }
+                Some(token) => return Err(ParserError::ExpectedTimeoutGot(token)),
+                None => return Err(ParserError::ExpectedTimeoutGot(lexer::Token::Eol)),
+            },
+            Some(lexer::Token::Nice) => match tokens.next() {
+                Some(lexer::Token::Ident(nice)) => builder = builder.nice(limits::nice(nice)?),
+                Some(token) => return Err(ParserError::ExpectedNiceGot(token)),
+                None => return Err(ParserError::ExpectedNiceGot(lexer::Token::Eol)),
+            },
+            Some(lexer::Token::Umask) => match tokens.next() {
+                Some(lexer::Token::Ident(umask)) => {
+                    builder = builder.umask(settings::umask(umask)?)
+                }
+                Some(token) => return Err(ParserError::ExpectedUmaskGot(token)),
+                None => return Err(ParserError::ExpectedUmaskGot(lexer::Token::Eol)),
+            },
+            Some(lexer::Token::Cwd) => match tokens.next() {
+                Some(lexer::Token::Ident(dir)) => builder = builder.cwd(dir),
+                Some(token) => return Err(ParserError::ExpectedCwdGot(token)),
+                None => return Err(ParserError::ExpectedCwdGot(lexer::Token::Eol)),
+            },
+            Some(lexer::Token::AllowDangerousEnv) => {
+                builder = builder.allow_dangerous_env(get_ident_or_list(tokens)?)
+            }
             Some(lexer::Token::Ident(user_identity)) => {
                 builder = builder.identity_name(user_identity);
                 return Ok(builder);
             }
+            Some(lexer::Token::OpenBrace) => {
+                return Ok(get_list(tokens)?
+                    .into_iter()
+                    .fold(builder, |builder, identity| builder.identity_name(identity)));
+            }
             Some(token) => return Err(ParserError::ExpectedOptionOrIdentityGot(token)),
             None => return Err(ParserError::ExpectedOptionOrIdentityGot(lexer::Token::Eol)),
         }
     }
 }
 
-///Moves forward the iterator until the next rule is encountered. (Permit | Deny is found.)
-fn go_until_next_rule<'a, T: Iterator<Item = lexer::Token<'a>>>(
-    tokens: &mut std::iter::Peekable<T>,
-) {
+///Takes either a single identifier, or a brace delimited list of them.
+fn get_ident_or_list<'a, T: Iterator<Item = lexer::Token<'a>>>(
+    tokens: &mut T,
+) -> Result<Vec<&'a str>, ParserError<'a>> {
+    get_words_or_list(tokens, ident)
+}
+
+///Takes the identifiers out of a brace delimited list such as `{ alice bob :ops }`.
+///Expects the opening brace to already be consumed, and consumes the closing brace.
+fn get_list<'a, T: Iterator<Item = lexer::Token<'a>>>(
+    tokens: &mut T,
+) -> Result<Vec<&'a str>, ParserError<'a>> {
+    get_word_list(tokens, ident)
+}
+
+///Returns the identifier, if the token is one.
+fn ident<'a>(token: &lexer::Token<'a>) -> Option<&'a str> {
+    match token {
+        lexer::Token::Ident(ident) => Some(ident),
+        _ => None,
+    }
+}
+
+///Takes either a single word, or a brace delimited list of them, where word decides which
+///tokens count as words and what they say.
+fn get_words_or_list<'a, T: Iterator<Item = lexer::Token<'a>>>(
+    tokens: &mut T,
+    word: fn(&lexer::Token<'a>) -> Option<&'a str>,
+) -> Result<Vec<&'a str>, ParserError<'a>> {
+    match tokens.next() {
+        Some(lexer::Token::OpenBrace) => get_word_list(tokens, word),
+        Some(token) => match word(&token) {
+            Some(word) => Ok(vec![word]),
+            None => Err(ParserError::ExpectedIdentOrListGot(token)),
+        },
+        None => Err(ParserError::ExpectedIdentOrListGot(lexer::Token::Eol)),
+    }
+}
+
+///Same as get_list, with word deciding what counts as a word.
+fn get_word_list<'a, T: Iterator<Item = lexer::Token<'a>>>(
+    tokens: &mut T,
+    word: fn(&lexer::Token<'a>) -> Option<&'a str>,
+) -> Result<Vec<&'a str>, ParserError<'a>> {
+    let mut words = vec![];
+    loop {
+        match tokens.next() {
+            Some(lexer::Token::CloseBrace) if words.is_empty() => {
+                return Err(ParserError::EmptyList)
+            }
+            Some(lexer::Token::CloseBrace) => return Ok(words),
+            Some(token) => match word(&token) {
+                Some(word) => words.push(word),
+                None => return Err(ParserError::ExpectedListItemGot(token)),
+            },
+            None => return Err(ParserError::ExpectedListItemGot(lexer::Token::Eol)),
+        }
+    }
+}
+
+///Takes the limits out of `rlimit nofile 4096` or `rlimit { nofile 4096 as 2G }`,
+///after the rlimit token.
+fn get_rlimits<'a, T: Iterator<Item = lexer::Token<'a>>>(
+    tokens: &mut T,
+) -> Result<Vec<limits::Rlimit>, ParserError<'a>> {
+    match tokens.next() {
+        Some(lexer::Token::OpenBrace) => (),
+        Some(lexer::Token::Ident(name)) => return Ok(vec![get_rlimit(name, tokens)?]),
+        //`as` is a keyword elsewhere, but here it's the address space limit.
+        Some(lexer::Token::As) => return Ok(vec![get_rlimit("as", tokens)?]),
+        Some(token) => return Err(ParserError::ExpectedRlimitGot(token)),
+        None => return Err(ParserError::ExpectedRlimitGot(lexer::Token::Eol)),
+    }
+    let mut rlimits = vec![];
     loop {
-        let next_item = tokens.peek();
-        if next_item == Some(&lexer::Token::Permit)
-            || next_item == Some(&lexer::Token::Deny)
-            || next_item == None
-        {
+        match tokens.next() {
+            Some(lexer::Token::Ident(name)) => rlimits.push(get_rlimit(name, tokens)?),
+            Some(lexer::Token::As) => rlimits.push(get_rlimit("as", tokens)?),
+            Some(lexer::Token::CloseBrace) if rlimits.is_empty() => {
+                return Err(ParserError::EmptyList)
+            }
+            Some(lexer::Token::CloseBrace) => return Ok(rlimits),
+            Some(token) => return Err(ParserError::ExpectedRlimitGot(token)),
+            None => return Err(ParserError::ExpectedRlimitGot(lexer::Token::Eol)),
+        }
+    }
+}
+
+///Takes the value of the limit on the named resource.
+fn get_rlimit<'a, T: Iterator<Item = lexer::Token<'a>>>(
+    name: &'a str,
+    tokens: &mut T,
+) -> Result<limits::Rlimit, ParserError<'a>> {
+    match tokens.next() {
+        Some(lexer::Token::Ident(value)) => limits::rlimit(name, value),
+        Some(token) => Err(ParserError::ExpectedRlimitGot(token)),
+        None => Err(ParserError::ExpectedRlimitGot(lexer::Token::Eol)),
+    }
+}
+
+///Moves forward the iterator until the next entry is encountered. (See starts_entry.)
+fn go_until_next_rule(tokens: &mut Tokens<'_>) {
+    while let Some(token) = tokens.peek() {
+        if starts_entry(token) {
             return;
-        } else {
-            tokens.next();
         }
+        tokens.next();
     }
 }
+
+///Returns whether the token starts an entry of the config. (Permit | Deny | Alias | Include)
+///Settings which are also rule options, such as path, aren't counted, since they could just
+///as well be in the middle of the broken rule.
+fn starts_entry(token: &lexer::Token<'_>) -> bool {
+    matches!(
+        token,
+        lexer::Token::Permit
+            | lexer::Token::Deny
+            | lexer::Token::Alias
+            | lexer::Token::Include
+            | lexer::Token::Export
+            | lexer::Token::AuditLog
+            | lexer::Token::Logger
+    )
+}
@@ -9,6 +9,46 @@ pub enum ParserError<'a> {
     ExpectedCmdNameGot(super::lexer::Token<'a>),
     ExpectedTargetGot(super::lexer::Token<'a>),
     ExpectedCmdPathGot(super::lexer::Token<'a>),
+    ExpectedArgsGot(super::lexer::Token<'a>),
+    ExpectedArgGot(super::lexer::Token<'a>),
+    ExpectedListItemGot(super::lexer::Token<'a>),
+    ExpectedIdentOrListGot(super::lexer::Token<'a>),
+    EmptyList,
+    ExpectedAliasNameGot(super::lexer::Token<'a>),
+    ExpectedEqualsGot(super::lexer::Token<'a>),
+    ExpectedAliasValueGot(super::lexer::Token<'a>),
+    InvalidAliasName(&'a str),
+    DuplicateAlias(&'a str),
+    UndefinedAlias(&'a str),
+    RecursiveAlias(&'a str),
+    ExpectedIncludePathGot(super::lexer::Token<'a>),
+    ExpectedRegexGot(super::lexer::Token<'a>),
+    InvalidRegex(&'a str, String),
+    ExpectedDigestGot(super::lexer::Token<'a>),
+    InvalidDigest(&'a str),
+    InvalidPathDir(&'a str),
+    UnknownExportVar(&'a str),
+    ExpectedCwdGot(super::lexer::Token<'a>),
+    InvalidCwd(&'a str),
+    ExpectedUmaskGot(super::lexer::Token<'a>),
+    InvalidUmask(&'a str),
+    ExpectedRlimitGot(super::lexer::Token<'a>),
+    UnknownRlimit(&'a str),
+    InvalidRlimit(&'a str),
+    ExpectedNiceGot(super::lexer::Token<'a>),
+    InvalidNice(&'a str),
+    ExpectedTimeoutGot(super::lexer::Token<'a>),
+    InvalidTimeout(&'a str),
+    UnknownCapability(&'a str),
+    ExpectedSeccompGot(super::lexer::Token<'a>),
+    InvalidSeccompProfile(&'a str),
+    ExpectedChrootGot(super::lexer::Token<'a>),
+    InvalidChroot(&'a str),
+    ExpectedEolGot(super::lexer::Token<'a>),
+    ExpectedAuditLogGot(super::lexer::Token<'a>),
+    InvalidAuditLog(&'a str),
+    ExpectedLoggerGot(super::lexer::Token<'a>),
+    UnknownLogger(&'a str),
 }
 
 impl<'a> std::fmt::Display for ParserError<'a> {
@@ -28,8 +68,171 @@ impl<'a> std::fmt::Display for ParserError<'a> {
             Self::ExpectedCmdNameGot(token) => {
                 write!(f, "Expected a command name got \"{}\".", token)
             }
-            Self::ExpectedTargetGot(token) => write!(f, "Expected target user got \"{}\".",token),
-            Self::ExpectedCmdPathGot(token) => write!(f, "Expected cmd path got \"{}\"", token)
+            Self::ExpectedTargetGot(token) => write!(f, "Expected target user got \"{}\".", token),
+            Self::ExpectedCmdPathGot(token) => write!(f, "Expected cmd path got \"{}\"", token),
+            Self::ExpectedArgsGot(token) => write!(
+                f,
+                "Expected args, digest or the end of the rule got \"{}\". \
+                 Options go before the identity.",
+                token
+            ),
+            Self::ExpectedArgGot(token) => write!(f, "Expected an argument got \"{}\".", token),
+            Self::ExpectedListItemGot(token) => {
+                write!(
+                    f,
+                    "Expected a list item or closing brace got \"{}\".",
+                    token
+                )
+            }
+            Self::ExpectedIdentOrListGot(token) => write!(
+                f,
+                "Expected an identifier or a list in braces got \"{}\".",
+                token
+            ),
+            Self::EmptyList => write!(f, "Expected at least one item between braces."),
+            Self::ExpectedAliasNameGot(token) => {
+                write!(f, "Expected an alias name got \"{}\".", token)
+            }
+            Self::ExpectedEqualsGot(token) => write!(f, "Expected \"=\" got \"{}\".", token),
+            Self::ExpectedAliasValueGot(token) => write!(
+                f,
+                "Expected an alias value (an identifier or a list in braces) got \"{}\".",
+                token
+            ),
+            Self::InvalidAliasName(name) => write!(
+                f,
+                "Alias name \"{}\" should be upper case letters, digits and underscores.",
+                name
+            ),
+            Self::DuplicateAlias(name) => write!(f, "Alias \"{}\" is defined twice.", name),
+            Self::UndefinedAlias(name) => write!(
+                f,
+                "Alias \"{}\" isn't defined. A name in upper case that isn't an alias has to be quoted.",
+                name
+            ),
+            Self::RecursiveAlias(name) => write!(f, "Alias \"{}\" refers to itself.", name),
+            Self::ExpectedRegexGot(token) => {
+                write!(f, "Expected a regex such as /^[a-z]+$/ got \"{}\".", token)
+            }
+            Self::InvalidRegex(regex, e) => write!(f, "Invalid regex {}: {}", regex, e),
+            Self::ExpectedDigestGot(token) => write!(
+                f,
+                "Expected a digest such as sha256:<64 hex digits> got \"{}\".",
+                token
+            ),
+            Self::InvalidDigest(digest) => write!(
+                f,
+                "Invalid digest \"{}\", expected sha256: followed by 64 hex digits.",
+                digest
+            ),
+            Self::ExpectedUmaskGot(token) => write!(
+                f,
+                "Expected a umask such as 027 or preserve got \"{}\".",
+                token
+            ),
+            Self::InvalidUmask(umask) => write!(
+                f,
+                "Invalid umask \"{}\", expected an octal number up to 777 or preserve.",
+                umask
+            ),
+            Self::ExpectedRlimitGot(token) => {
+                write!(f, "Expected a limit such as nofile 4096 got \"{}\".", token)
+            }
+            Self::UnknownRlimit(name) => write!(
+                f,
+                "Unknown resource \"{}\", expected one of as, core, cpu, data, fsize, \
+                 memlock, nofile, nproc or stack.",
+                name
+            ),
+            Self::InvalidRlimit(value) => write!(
+                f,
+                "Invalid limit \"{}\", expected a number (with an optional K, M, G or T \
+                 suffix) or infinity.",
+                value
+            ),
+            Self::ExpectedNiceGot(token) => {
+                write!(f, "Expected a nice level such as 10 got \"{}\".", token)
+            }
+            Self::InvalidNice(value) => write!(
+                f,
+                "Invalid nice level \"{}\", expected a number from -20 to 19.",
+                value
+            ),
+            Self::ExpectedTimeoutGot(token) => {
+                write!(f, "Expected a timeout such as 15m got \"{}\".", token)
+            }
+            Self::InvalidTimeout(value) => write!(
+                f,
+                "Invalid timeout \"{}\", expected a number of seconds, or a number followed \
+                 by s, m, h or d.",
+                value
+            ),
+            Self::UnknownCapability(name) => write!(
+                f,
+                "Unknown capability \"{}\", expected a name such as cap_net_raw.",
+                name
+            ),
+            Self::ExpectedSeccompGot(token) => write!(
+                f,
+                "Expected a seccomp profile name such as \"no-ptrace\" got \"{}\".",
+                token
+            ),
+            Self::InvalidSeccompProfile(name) => write!(
+                f,
+                "Invalid seccomp profile name {}, expected letters, digits, '.', '_' and '-'.",
+                name
+            ),
+            Self::ExpectedChrootGot(token) => write!(
+                f,
+                "Expected a directory such as /srv/jail got \"{}\".",
+                token
+            ),
+            Self::InvalidChroot(dir) => {
+                write!(f, "Chroot \"{}\" should be an absolute path.", dir)
+            }
+            Self::ExpectedAuditLogGot(token) => write!(
+                f,
+                "Expected a file such as /var/log/doas/audit.json got \"{}\".",
+                token
+            ),
+            Self::InvalidAuditLog(file) => {
+                write!(f, "Audit log \"{}\" should be an absolute path.", file)
+            }
+            Self::ExpectedLoggerGot(token) => {
+                write!(f, "Expected syslog or journald got \"{}\".", token)
+            }
+            Self::UnknownLogger(logger) => write!(
+                f,
+                "Unknown logger \"{}\", expected syslog or journald.",
+                logger
+            ),
+            Self::ExpectedEolGot(token) => {
+                write!(f, "Expected the end of the line got \"{}\".", token)
+            }
+            Self::ExpectedCwdGot(token) => write!(
+                f,
+                "Expected a directory such as /srv/app or ~ got \"{}\".",
+                token
+            ),
+            Self::InvalidCwd(dir) => write!(
+                f,
+                "Working directory \"{}\" should be an absolute path or ~.",
+                dir
+            ),
+            Self::UnknownExportVar(var) => write!(
+                f,
+                "\"{}\" doesn't match any variable doas sets. Expected one of {}.",
+                var,
+                super::settings::CALLER_VARS.join(", ")
+            ),
+            Self::InvalidPathDir(dir) => write!(
+                f,
+                "Path directory \"{}\" should be an absolute path without any ':'.",
+                dir
+            ),
+            Self::ExpectedIncludePathGot(token) => {
+                write!(f, "Expected a path to include got \"{}\".", token)
+            }
         }
     }
 }
@@ -0,0 +1,242 @@
+//! This module defines the Pattern type, which is what a rule's cmd and args are matched with.
+//! Patterns are literal unless they contain an unquoted, unescaped `*`, `?` or `[...]`,
+//! in which case they're fnmatch style globs. Quoting or escaping a wildcard always
+//! makes it match literally, so `"nginx@*"` only matches the string `nginx@*`.
+//! Args can also be regexes, written `~ /regex/`, which always have to match the whole arg.
+use regex::Regex;
+
+///A literal string, glob or regex, as written in a rule.
+#[derive(Debug)]
+pub enum Pattern {
+    Literal(String),
+    ///The glob as written in the config, and what it was compiled to.
+    Glob(String, Vec<GlobToken>),
+    ///The regex as written between the slashes, and the anchored version of it.
+    Regex(String, Regex),
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Literal(a), Self::Literal(b)) => a == b,
+            (Self::Glob(a, _), Self::Glob(b, _)) => a == b,
+            (Self::Regex(a, _), Self::Regex(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Pattern {}
+
+///A piece of a glob.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GlobToken {
+    Char(char),
+    ///`*`, any run of characters.
+    AnyString,
+    ///`?`, any single character.
+    AnyChar,
+    ///`[...]` or `[!...]`, any character in (or not in) the class.
+    Class(bool, Vec<(char, char)>),
+}
+
+impl Pattern {
+    ///Compiles the pattern as written in the config, still containing any quotes and escapes.
+    pub fn new(raw: &str) -> Self {
+        //Each character, along with whether it was quoted or escaped.
+        let mut chars = vec![];
+        let (mut in_quotes, mut escaped) = (false, false);
+        for c in raw.chars() {
+            if escaped {
+                chars.push((c, true));
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_quotes = !in_quotes;
+            } else {
+                chars.push((c, in_quotes));
+            }
+        }
+
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            let token = match chars[i] {
+                ('*', false) => GlobToken::AnyString,
+                ('?', false) => GlobToken::AnyChar,
+                ('[', false) => match parse_class(&chars[i + 1..]) {
+                    Some((class, len)) => {
+                        i += len;
+                        class
+                    }
+                    None => GlobToken::Char('['),
+                },
+                (c, _) => GlobToken::Char(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+
+        if tokens.iter().any(|t| !matches!(t, GlobToken::Char(_))) {
+            Self::Glob(raw.to_owned(), tokens)
+        } else {
+            Self::Literal(chars.into_iter().map(|(c, _)| c).collect())
+        }
+    }
+
+    ///Compiles the regex, anchoring it so it has to match the whole string.
+    pub fn regex(regex: &str) -> Result<Self, regex::Error> {
+        let anchored = Regex::new(&format!("^(?:{})$", regex))?;
+        Ok(Self::Regex(regex.to_owned(), anchored))
+    }
+
+    pub fn is_literal(&self) -> bool {
+        matches!(self, Self::Literal(_))
+    }
+
+    ///Returns whether the pattern only matches absolute paths.
+    pub fn is_absolute(&self) -> bool {
+        match self {
+            Self::Literal(literal) => literal.starts_with('/'),
+            Self::Glob(_, tokens) => tokens.first() == Some(&GlobToken::Char('/')),
+            Self::Regex(..) => false,
+        }
+    }
+
+    ///Returns whether the string matches, exactly as given, so any whitespace around it has to
+    ///match too. Wildcards match any character, including '/'.
+    pub fn matches(&self, s: &str) -> bool {
+        match self {
+            Self::Literal(literal) => literal == s,
+            Self::Glob(_, tokens) => glob_matches(tokens, &s.chars().collect::<Vec<_>>(), false),
+            Self::Regex(_, regex) => regex.is_match(s),
+        }
+    }
+
+    ///Returns whether the path matches. Wildcards never match a '/', so
+    ///`/usr/local/bin/deploy-*` doesn't match `/usr/local/bin/deploy-x/../../sh`.
+    pub fn matches_path(&self, s: &str) -> bool {
+        match self {
+            Self::Literal(literal) => literal == s,
+            Self::Glob(_, tokens) => glob_matches(tokens, &s.chars().collect::<Vec<_>>(), true),
+            Self::Regex(_, regex) => regex.is_match(s),
+        }
+    }
+}
+
+///Parses the rest of a character class, after the opening '['.
+///Returns the class and the number of characters it took up (including the closing ']'),
+///or None if the class is never closed.
+fn parse_class(chars: &[(char, bool)]) -> Option<(GlobToken, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some(('!', false)) | Some(('^', false)));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = vec![];
+    //A ']' right at the start of the class is part of it rather than closing it.
+    let start = i;
+    loop {
+        let (c, _) = *chars.get(i)?;
+        if chars[i] == (']', false) && i != start {
+            return Some((GlobToken::Class(negated, ranges), i + 1));
+        }
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some(('-', false)), Some(&(end, _))) if end != ']' => {
+                ranges.push((c, end));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+fn glob_matches(tokens: &[GlobToken], chars: &[char], is_path: bool) -> bool {
+    let can_match = |c: char| !(is_path && c == '/');
+    match tokens.split_first() {
+        None => chars.is_empty(),
+        Some((GlobToken::AnyString, rest)) => {
+            let longest = chars.iter().take_while(|&&c| can_match(c)).count();
+            (0..=longest).any(|i| glob_matches(rest, &chars[i..], is_path))
+        }
+        Some((token, rest)) => match chars.split_first() {
+            Some((&c, remaining)) => {
+                let matches = match token {
+                    GlobToken::Char(expected) => c == *expected,
+                    GlobToken::AnyChar => can_match(c),
+                    GlobToken::Class(negated, ranges) => {
+                        can_match(c)
+                            && ranges.iter().any(|&(start, end)| start <= c && c <= end) != *negated
+                    }
+                    GlobToken::AnyString => unreachable!(),
+                };
+                matches && glob_matches(rest, remaining, is_path)
+            }
+            None => false,
+        },
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Literal(literal) => write!(f, "{}", super::rules::quoted(literal)),
+            Self::Glob(raw, _) => write!(f, "{}", raw),
+            Self::Regex(regex, _) if regex.contains(char::is_whitespace) => {
+                write!(f, "~ \"/{}/\"", regex)
+            }
+            Self::Regex(regex, _) => write!(f, "~ /{}/", regex),
+        }
+    }
+}
+
+///Tests for compiling and matching globs.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_patterns() {
+        assert!(Pattern::new("systemctl").is_literal());
+        assert!(Pattern::new("\"nginx@*\"").is_literal());
+        assert!(Pattern::new(r#"nginx@\*"#).is_literal());
+        assert!(Pattern::new("[unclosed").is_literal());
+        assert!(Pattern::new("\"nginx@*\"").matches("nginx@*"));
+        assert!(!Pattern::new("\"nginx@*\"").matches("nginx@web"));
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        assert!(Pattern::new("nginx@*").matches("nginx@web"));
+        assert!(Pattern::new("nginx@*").matches("nginx@"));
+        assert!(!Pattern::new("nginx@*").matches("apache@web"));
+        assert!(Pattern::new("\"nginx\"@*").matches("nginx@web"));
+        assert!(Pattern::new("file?.txt").matches("file1.txt"));
+        assert!(!Pattern::new("file?.txt").matches("file10.txt"));
+        assert!(Pattern::new("[a-c]*").matches("banana"));
+        assert!(!Pattern::new("[!a-c]*").matches("banana"));
+        assert!(Pattern::new("[]x]").matches("]"));
+    }
+
+    #[test]
+    fn test_regex_patterns_are_anchored() {
+        let pattern = Pattern::regex(r"[a-z0-9-]+\.service").unwrap();
+        assert!(pattern.matches("nginx.service"));
+        assert!(!pattern.matches("nginx.service; reboot"));
+        assert!(!pattern.matches("x/nginx.service"));
+        assert!(Pattern::regex("a|b").unwrap().matches("b"));
+        assert!(!Pattern::regex("a|b").unwrap().matches("ab"));
+    }
+
+    #[test]
+    fn test_path_patterns() {
+        let pattern = Pattern::new("/usr/local/bin/deploy-*");
+        assert!(pattern.matches_path("/usr/local/bin/deploy-web"));
+        assert!(!pattern.matches_path("/usr/local/bin/deploy-x/../../sh"));
+        assert!(pattern.matches("/usr/local/bin/deploy-x/y"));
+    }
+}
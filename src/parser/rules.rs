@@ -2,19 +2,29 @@
 //! in order to represent any given rule in the config.
 //! A rule is defined as a series of arguments that apply to doas to one particular user.
 //! A real implementation of doas should always use the last match of the config.
+use super::aliases::Aliases;
+use super::caps::Capability;
+use super::limits::{self, Rlimit};
+use super::pattern::Pattern;
+use super::settings::Umask;
 use super::ParserError;
 use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 
 //TODO: Remove the Comment enum.
 ///Represents a Rule.
-///The first String is the user the rule applies too
+///The first field is the list of users and groups the rule applies too
 ///Config args are the arguments that apply to the user
 ///(aside from permit or deny.)
 ///Created with RuleBuilder.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Rule {
-    Permit(UserOrGroup, ConfigArgs),
-    Deny(UserOrGroup, ConfigArgs),
+    Permit(Vec<UserOrGroup>, ConfigArgs),
+    Deny(Vec<UserOrGroup>, ConfigArgs),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -30,22 +40,108 @@ pub struct ConfigArgs {
     keep_env: bool,
     no_pass: bool,
     set_env: HashMap<String, String>,
-    target: Option<String>,
-    cmd: Option<String>,
-    args: Option<Vec<String>>,
+    targets: Option<Vec<String>>,
+    cmds: Option<Vec<Pattern>>,
+    args: Option<Vec<Pattern>>,
+    ///Set by a trailing `*` in args, allows any args after the ones given.
+    more_args: bool,
+    digest: Option<Digest>,
+    ///Directories to look commands up in, and the PATH to run them with.
+    path: Option<Vec<String>>,
+    ///Dangerous env vars (like LD_PRELOAD) the rule still passes on to the command.
+    allow_dangerous_env: Vec<String>,
+    ///The directory to run the command in, unless -D gives another.
+    cwd: Option<Cwd>,
+    ///Set by `nochdir`, forbids picking the directory with -D.
+    no_chdir: bool,
+    umask: Option<Umask>,
+    rlimits: Vec<Rlimit>,
+    nice: Option<i32>,
+    ///How long the command can run before it's killed.
+    timeout: Option<Duration>,
+    ///If not empty, the command runs with only these capabilities rather than as full root.
+    caps: Vec<Capability>,
+    ///Set by `nonewprivs`, stops the command gaining privileges through setuid binaries.
+    no_new_privs: bool,
+    ///The name of the seccomp profile to run the command under.
+    seccomp: Option<String>,
+    sandbox: Sandbox,
+    ///The directory to chroot into. The command is looked up and run inside it.
+    chroot: Option<String>,
+    ///Set by `usepty`, runs the command on a pty of its own rather than the caller's terminal.
+    use_pty: bool,
+    ///Set by `record`, records the session to a file. This implies use_pty.
+    record: bool,
+}
+
+///The namespaces a rule runs its command in, given with `private-tmp`, `readonly-root`
+///and `no-network`.
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+pub struct Sandbox {
+    pub private_tmp: bool,
+    pub readonly_root: bool,
+    pub no_network: bool,
+}
+
+///The directory a rule runs its command in, given with `cwd`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Cwd {
+    ///`cwd ~`, the target user's home.
+    Home,
+    Dir(String),
+}
+
+impl std::fmt::Display for Cwd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Home => write!(f, "~"),
+            Self::Dir(dir) => write!(f, "{}", quoted(dir)),
+        }
+    }
+}
+
+///The SHA-256 digest a command's binary has to have, pinned with `digest sha256:...`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Digest(String);
+
+impl Digest {
+    ///Parses a digest written as sha256: followed by 64 hex digits.
+    pub fn parse(digest: &str) -> Option<Self> {
+        let hex = digest.strip_prefix("sha256:")?;
+        if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            Some(Self(hex.to_ascii_lowercase()))
+        } else {
+            None
+        }
+    }
+
+    ///The digest as lower case hex.
+    pub fn as_hex(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256:{}", self.0)
+    }
 }
 
 impl Rule {
     ///Returns a boolean representing if the user is allowed to run the command or not
     ///Returns None in the case that the rule dosen't match on the given args.
+    ///cmd is the command as the user typed it, resolved_cmd the canonical path of the binary
+    ///it refers to (if there is one).
     pub fn is_allowed<'a, T: std::fmt::Debug + IntoIterator<Item = &'a str>>(
         &self,
         name: &str,
         groups: T,
         cmd: &str,
+        resolved_cmd: Option<&Path>,
         cmd_args: &[&str],
         target: &str,
     ) -> Option<bool> {
+        let cmd = (cmd, resolved_cmd);
         match self {
             Self::Permit(user, conf_args) => {
                 if check_if_match(user, name, groups, target, cmd, cmd_args, conf_args) {
@@ -63,10 +159,22 @@ impl Rule {
             }
         }
     }
-    pub fn get_identity(&self) -> &UserOrGroup {
+    ///Returns whether the rule is written for the given user, or one of the given groups.
+    ///This ignores the target and command of the rule.
+    pub fn applies_to<'a, T: IntoIterator<Item = &'a str>>(&self, name: &str, groups: T) -> bool {
+        identity_matches(self.get_identities(), name, groups)
+    }
+    pub fn get_identities(&self) -> &[UserOrGroup] {
         match self {
-            Self::Permit(user, _) => user,
-            Self::Deny(user, _) => user,
+            Self::Permit(users, _) => users,
+            Self::Deny(users, _) => users,
+        }
+    }
+    ///Returns the users the rule allows running as, or None if it allows any.
+    pub fn get_targets(&self) -> Option<&[String]> {
+        match self {
+            Self::Permit(_, args) => args.targets.as_deref(),
+            Self::Deny(_, args) => args.targets.as_deref(),
         }
     }
     pub fn get_set_env(&self) -> &HashMap<String, String> {
@@ -75,12 +183,114 @@ impl Rule {
             Self::Deny(_, args) => &args.set_env,
         }
     }
+    pub fn get_keep_env(&self) -> bool {
+        match self {
+            Self::Permit(_, args) => args.keep_env,
+            Self::Deny(_, args) => args.keep_env,
+        }
+    }
+    pub fn get_allow_dangerous_env(&self) -> &[String] {
+        match self {
+            Self::Permit(_, args) => &args.allow_dangerous_env,
+            Self::Deny(_, args) => &args.allow_dangerous_env,
+        }
+    }
+    pub fn get_cwd(&self) -> Option<&Cwd> {
+        match self {
+            Self::Permit(_, args) => args.cwd.as_ref(),
+            Self::Deny(_, args) => args.cwd.as_ref(),
+        }
+    }
+    pub fn get_no_chdir(&self) -> bool {
+        match self {
+            Self::Permit(_, args) => args.no_chdir,
+            Self::Deny(_, args) => args.no_chdir,
+        }
+    }
+    pub fn get_umask(&self) -> Option<Umask> {
+        match self {
+            Self::Permit(_, args) => args.umask,
+            Self::Deny(_, args) => args.umask,
+        }
+    }
+    pub fn get_rlimits(&self) -> &[Rlimit] {
+        match self {
+            Self::Permit(_, args) => &args.rlimits,
+            Self::Deny(_, args) => &args.rlimits,
+        }
+    }
+    pub fn get_nice(&self) -> Option<i32> {
+        match self {
+            Self::Permit(_, args) => args.nice,
+            Self::Deny(_, args) => args.nice,
+        }
+    }
+    pub fn get_timeout(&self) -> Option<Duration> {
+        match self {
+            Self::Permit(_, args) => args.timeout,
+            Self::Deny(_, args) => args.timeout,
+        }
+    }
+    pub fn get_caps(&self) -> &[Capability] {
+        match self {
+            Self::Permit(_, args) => &args.caps,
+            Self::Deny(_, args) => &args.caps,
+        }
+    }
+    pub fn get_use_pty(&self) -> bool {
+        match self {
+            Self::Permit(_, args) => args.use_pty,
+            Self::Deny(_, args) => args.use_pty,
+        }
+    }
+    pub fn get_record(&self) -> bool {
+        match self {
+            Self::Permit(_, args) => args.record,
+            Self::Deny(_, args) => args.record,
+        }
+    }
+    pub fn get_no_new_privs(&self) -> bool {
+        match self {
+            Self::Permit(_, args) => args.no_new_privs,
+            Self::Deny(_, args) => args.no_new_privs,
+        }
+    }
+    pub fn get_seccomp(&self) -> Option<&str> {
+        match self {
+            Self::Permit(_, args) => args.seccomp.as_deref(),
+            Self::Deny(_, args) => args.seccomp.as_deref(),
+        }
+    }
+    pub fn get_sandbox(&self) -> Sandbox {
+        match self {
+            Self::Permit(_, args) => args.sandbox,
+            Self::Deny(_, args) => args.sandbox,
+        }
+    }
+    pub fn get_chroot(&self) -> Option<&Path> {
+        match self {
+            Self::Permit(_, args) => args.chroot.as_deref().map(Path::new),
+            Self::Deny(_, args) => args.chroot.as_deref().map(Path::new),
+        }
+    }
     pub fn get_no_pass(&self) -> bool {
         match self {
             Self::Permit(_, args) => args.no_pass,
             Self::Deny(_, args) => args.no_pass,
         }
     }
+    pub fn get_digest(&self) -> Option<&Digest> {
+        match self {
+            Self::Permit(_, args) => args.digest.as_ref(),
+            Self::Deny(_, args) => args.digest.as_ref(),
+        }
+    }
+    pub fn get_path(&self) -> Option<&[String]> {
+        match self {
+            Self::Permit(_, args) => args.path.as_deref(),
+            Self::Deny(_, args) => args.path.as_deref(),
+        }
+    }
     pub fn get_persist(&self) -> bool {
         match self {
             Self::Permit(_, args) => args.persist,
@@ -89,38 +299,288 @@ impl Rule {
     }
 }
 
+impl std::fmt::Display for UserOrGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User(name) => write!(f, "{}", quoted(name)),
+            Self::Group(name) => write!(f, ":{}", quoted(name)),
+        }
+    }
+}
+
+///Writes the rule back out in config syntax, with aliases expanded.
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (identities, args) = match self {
+            Self::Permit(identities, args) => {
+                write!(f, "permit")?;
+                (identities, args)
+            }
+            Self::Deny(identities, args) => {
+                write!(f, "deny")?;
+                (identities, args)
+            }
+        };
+        if args.no_pass {
+            write!(f, " nopass")?;
+        }
+        if args.persist {
+            write!(f, " persist")?;
+        }
+        if args.keep_env {
+            write!(f, " keepenv")?;
+        }
+        if !args.set_env.is_empty() {
+            let mut set_env: Vec<_> = args.set_env.iter().collect();
+            set_env.sort();
+            write!(f, " setenv {{")?;
+            for (key, value) in set_env {
+                write!(f, " {}={}", quoted(key), quoted(value))?;
+            }
+            write!(f, " }}")?;
+        }
+        if !args.allow_dangerous_env.is_empty() {
+            write!(
+                f,
+                " allow_dangerous_env {}",
+                display_list(&args.allow_dangerous_env)
+            )?;
+        }
+        if let Some(cwd) = &args.cwd {
+            write!(f, " cwd {}", cwd)?;
+        }
+        if args.no_chdir {
+            write!(f, " nochdir")?;
+        }
+        if let Some(umask) = args.umask {
+            write!(f, " umask {}", umask)?;
+        }
+        if !args.rlimits.is_empty() {
+            write!(f, " rlimit {}", display_list(&args.rlimits))?;
+        }
+        if let Some(nice) = args.nice {
+            write!(f, " nice {}", nice)?;
+        }
+        if let Some(timeout) = args.timeout {
+            write!(f, " timeout {}", limits::display_timeout(timeout))?;
+        }
+        if !args.caps.is_empty() {
+            write!(f, " caps {}", display_list(&args.caps))?;
+        }
+        if args.no_new_privs {
+            write!(f, " nonewprivs")?;
+        }
+        if let Some(profile) = &args.seccomp {
+            write!(f, " seccomp \"{}\"", profile)?;
+        }
+        if let Some(root) = &args.chroot {
+            write!(f, " chroot {}", quoted(root))?;
+        }
+        if args.sandbox.private_tmp {
+            write!(f, " private-tmp")?;
+        }
+        if args.sandbox.readonly_root {
+            write!(f, " readonly-root")?;
+        }
+        if args.sandbox.no_network {
+            write!(f, " no-network")?;
+        }
+        if args.use_pty {
+            write!(f, " usepty")?;
+        }
+        if args.record {
+            write!(f, " record")?;
+        }
+        if let Some(path) = &args.path {
+            write!(f, " path {{ {} }}", path.join(" "))?;
+        }
+        write!(f, " {}", display_list(identities))?;
+        if let Some(targets) = &args.targets {
+            write!(f, " as {}", display_list(targets))?;
+        }
+        if let Some(cmds) = &args.cmds {
+            write!(f, " cmd {}", display_list(cmds))?;
+        }
+        if let Some(digest) = &args.digest {
+            write!(f, " digest {}", digest)?;
+        }
+        if let Some(cmd_args) = &args.args {
+            write!(f, " args")?;
+            for arg in cmd_args {
+                write!(f, " {}", arg)?;
+            }
+            if args.more_args {
+                write!(f, " *")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+///Displays a single item on its own, and anything else as a brace delimited list.
+fn display_list<T: std::fmt::Display>(items: &[T]) -> String {
+    match items {
+        [item] => item.to_string(),
+        items => {
+            let items: Vec<_> = items.iter().map(|i| i.to_string()).collect();
+            format!("{{ {} }}", items.join(" "))
+        }
+    }
+}
+
+///Returns whether the seccomp profile name is safe to look up as a file name.
+fn is_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-_".contains(c))
+}
+
+///Quotes the string if it wouldn't be read back as a single literal identifier otherwise.
+pub(super) fn quoted(s: &str) -> std::borrow::Cow<'_, str> {
+    if !s.is_empty()
+        && s != "~"
+        && !s
+            .chars()
+            .any(|c| c.is_whitespace() || "\"\\{}=#*?[".contains(c))
+    {
+        return s.into();
+    }
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")).into()
+}
+
+///Helper function to check if a rule's cmd matches the command being run.
+///Absolute cmds are matched against the canonical path of the binary, so symlinks and
+///relative paths can't be used to get around them. Bare names only match commands typed
+///as a bare name, which were looked up in the safe PATH rather than wherever the caller wanted.
+///With a chroot, the binary's path is the one inside it, and so is the cmd's.
+fn cmd_matches(
+    conf_cmd: &Pattern,
+    cmd: &str,
+    resolved_cmd: Option<&Path>,
+    chroot: Option<&str>,
+) -> bool {
+    if !conf_cmd.is_absolute() {
+        return !cmd.contains('/') && conf_cmd.matches_path(cmd);
+    }
+    let resolved_cmd = match resolved_cmd.and_then(|p| p.to_str()) {
+        Some(resolved_cmd) => resolved_cmd,
+        None => return false,
+    };
+    if conf_cmd.matches_path(resolved_cmd) {
+        return true;
+    }
+    //The rule itself might name the binary through a symlink, such as /bin -> /usr/bin.
+    let conf_cmd = match conf_cmd {
+        Pattern::Literal(conf_cmd) => Path::new(conf_cmd),
+        _ => return false,
+    };
+    let canonical = match chroot {
+        Some(root) => Path::new(root)
+            .canonicalize()
+            .and_then(|root| canonicalize_in(&root, conf_cmd)),
+        None => conf_cmd.canonicalize(),
+    };
+    canonical.map_or(false, |conf_cmd| conf_cmd == Path::new(resolved_cmd))
+}
+
+///Canonicalizes path as it is inside the chroot at root, which has to be canonical itself.
+///Symlinks are followed as the chroot sees them: absolute ones from its root, and '..' never
+///leads out of it. Relative paths are relative to its root, and the path returned is inside it.
+pub fn canonicalize_in(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    //The components still to be followed, with the next one last.
+    let mut left: Vec<OsString> = vec![];
+    let push = |left: &mut Vec<OsString>, path: &Path| {
+        for component in path.components().rev() {
+            match component {
+                Component::Normal(name) => left.push(name.to_owned()),
+                Component::ParentDir => left.push("..".into()),
+                _ => {}
+            }
+        }
+    };
+    push(&mut left, path);
+    let mut resolved = PathBuf::from("/");
+    let mut links = 0;
+    while let Some(name) = left.pop() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+        let next = resolved.join(&name);
+        let outside = root.join(next.strip_prefix("/").unwrap_or(&next));
+        if !fs::symlink_metadata(&outside)?.file_type().is_symlink() {
+            resolved = next;
+            continue;
+        }
+        //The same limit as the kernel's, so a loop doesn't go on forever.
+        links += 1;
+        if links > 40 {
+            return Err(io::Error::from_raw_os_error(libc::ELOOP));
+        }
+        let target = fs::read_link(&outside)?;
+        if target.is_absolute() {
+            resolved = PathBuf::from("/");
+        }
+        push(&mut left, &target);
+    }
+    Ok(resolved)
+}
+
+///Helper function to check if the user, or one of their groups, is in the rule's identities.
+fn identity_matches<'a, T: IntoIterator<Item = &'a str>>(
+    rule_applies_to: &[UserOrGroup],
+    user_attempt_name: &str,
+    user_groups: T,
+) -> bool {
+    let user_groups: Vec<&str> = user_groups.into_iter().collect();
+    rule_applies_to.iter().any(|identity| match identity {
+        UserOrGroup::User(s) => s.as_str() == user_attempt_name,
+        UserOrGroup::Group(s) => user_groups.contains(&s.as_str()),
+    })
+}
+
 ///Helper function to check if a set of data matches with the rule.
 fn check_if_match<'a, T: std::fmt::Debug + IntoIterator<Item = &'a str>>(
-    rule_applies_to: &UserOrGroup,
+    rule_applies_to: &[UserOrGroup],
     user_attempt_name: &str,
     user_groups: T,
     target: &str,
-    cmd: &str,
+    (cmd, resolved_cmd): (&str, Option<&Path>),
     cmd_args: &[&str],
     conf_args: &ConfigArgs,
 ) -> bool {
-    match rule_applies_to {
-        UserOrGroup::User(s) if s.as_str() != user_attempt_name => return false,
-        UserOrGroup::Group(s) if user_groups.into_iter().all(|g| g != s.as_str()) => return false,
-        _ => (),
+    if !identity_matches(rule_applies_to, user_attempt_name, user_groups) {
+        return false;
     }
 
-    if let Some(rule_target) = &conf_args.target {
-        if target.trim() != rule_target.trim() {
+    if let Some(rule_targets) = &conf_args.targets {
+        if rule_targets.iter().all(|t| target.trim() != t.trim()) {
             return false;
         }
     }
 
-    if let Some(conf_cmd) = &conf_args.cmd {
-        if conf_cmd.trim() != cmd.trim() {
+    if let Some(conf_cmds) = &conf_args.cmds {
+        let chroot = conf_args.chroot.as_deref();
+        if !conf_cmds
+            .iter()
+            .any(|c| cmd_matches(c, cmd, resolved_cmd, chroot))
+        {
             return false;
         }
     }
-    if let Some(conf_cmd_args) = conf_args.args.clone() {
-        if !cmd_args
-            .iter()
-            .map(|s| s.trim())
-            .eq(conf_cmd_args.iter().map(|s| s.trim()))
+    if let Some(conf_cmd_args) = &conf_args.args {
+        let has_right_len = if conf_args.more_args {
+            cmd_args.len() >= conf_cmd_args.len()
+        } else {
+            cmd_args.len() == conf_cmd_args.len()
+        };
+        if !has_right_len
+            || !conf_cmd_args
+                .iter()
+                .zip(cmd_args)
+                .all(|(pattern, arg)| pattern.matches(arg))
         {
             return false;
         }
@@ -133,14 +593,30 @@ fn check_if_match<'a, T: std::fmt::Debug + IntoIterator<Item = &'a str>>(
 #[derive(Default)]
 pub struct RuleBuilder<'a> {
     rule_type: Option<RuleType>,
-    identity_name: Option<&'a str>,
+    identity_names: Vec<&'a str>,
     persist: bool,
     keep_env: bool,
     no_pass: bool,
     set_env: HashMap<&'a str, &'a str>,
-    target: Option<&'a str>,
-    cmd: Option<&'a str>,
+    targets: Vec<&'a str>,
+    cmds: Vec<&'a str>,
     args: Option<Vec<&'a str>>,
+    digest: Option<&'a str>,
+    path: Option<Vec<String>>,
+    allow_dangerous_env: Vec<&'a str>,
+    cwd: Option<&'a str>,
+    no_chdir: bool,
+    umask: Option<Umask>,
+    rlimits: Vec<Rlimit>,
+    nice: Option<i32>,
+    timeout: Option<Duration>,
+    caps: Vec<Capability>,
+    no_new_privs: bool,
+    seccomp: Option<&'a str>,
+    sandbox: Sandbox,
+    chroot: Option<&'a str>,
+    use_pty: bool,
+    record: bool,
 }
 enum RuleType {
     Permit,
@@ -177,27 +653,132 @@ impl<'a> RuleBuilder<'a> {
         Self { set_env: m, ..self }
     }
 
-    pub fn identity_name(self, name: &'a str) -> RuleBuilder<'a> {
+    pub fn cwd(self, dir: &'a str) -> Self {
         Self {
-            identity_name: Some(name),
+            cwd: Some(dir),
             ..self
         }
     }
 
-    pub fn no_pass(self) -> Self {
+    ///Sets the umask, which should already be checked.
+    pub fn umask(self, umask: Umask) -> Self {
         Self {
-            no_pass: true,
+            umask: Some(umask),
+            ..self
+        }
+    }
+
+    ///Adds a resource limit, replacing any earlier limit on the same resource.
+    pub fn rlimit(mut self, rlimit: Rlimit) -> Self {
+        self.rlimits.retain(|r| r.resource != rlimit.resource);
+        self.rlimits.push(rlimit);
+        self
+    }
+
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    ///Adds capabilities for the command to run with, which should already be looked up.
+    pub fn caps(mut self, caps: Vec<Capability>) -> Self {
+        self.caps.extend(caps);
+        self
+    }
+
+    pub fn use_pty(self) -> Self {
+        Self {
+            use_pty: true,
+            ..self
+        }
+    }
+
+    pub fn record(self) -> Self {
+        Self {
+            record: true,
+            ..self
+        }
+    }
+
+    pub fn no_new_privs(self) -> Self {
+        Self {
+            no_new_privs: true,
+            ..self
+        }
+    }
+
+    pub fn chroot(self, dir: &'a str) -> Self {
+        Self {
+            chroot: Some(dir),
             ..self
         }
     }
 
-    pub fn target(self, target_user: &'a str) -> RuleBuilder<'a> {
+    pub fn private_tmp(mut self) -> Self {
+        self.sandbox.private_tmp = true;
+        self
+    }
+
+    pub fn readonly_root(mut self) -> Self {
+        self.sandbox.readonly_root = true;
+        self
+    }
+
+    pub fn no_network(mut self) -> Self {
+        self.sandbox.no_network = true;
+        self
+    }
+
+    pub fn seccomp(self, profile: &'a str) -> Self {
+        Self {
+            seccomp: Some(profile),
+            ..self
+        }
+    }
+
+    pub fn nice(self, nice: i32) -> Self {
+        Self {
+            nice: Some(nice),
+            ..self
+        }
+    }
+
+    pub fn no_chdir(self) -> Self {
+        Self {
+            no_chdir: true,
+            ..self
+        }
+    }
+
+    ///Adds env vars to pass on to the command even though they're dangerous.
+    pub fn allow_dangerous_env(mut self, vars: Vec<&'a str>) -> Self {
+        self.allow_dangerous_env.extend(vars);
+        self
+    }
+
+    ///Adds a user (or :group) to the identities the rule applies to.
+    ///Can be called multiple times for rules given a list of identities.
+    pub fn identity_name(mut self, name: &'a str) -> RuleBuilder<'a> {
+        self.identity_names.push(name);
+        self
+    }
+
+    pub fn no_pass(self) -> Self {
         Self {
-            target: Some(target_user),
+            no_pass: true,
             ..self
         }
     }
 
+    ///Adds a user to the targets the rule allows running as.
+    ///Can be called multiple times for rules given a list of targets.
+    pub fn target(mut self, target_user: &'a str) -> RuleBuilder<'a> {
+        self.targets.push(target_user);
+        self
+    }
+
     pub fn persist(self) -> RuleBuilder<'a> {
         Self {
             persist: true,
@@ -205,9 +786,24 @@ impl<'a> RuleBuilder<'a> {
         }
     }
 
-    pub fn with_cmd(self, cmd: &'a str) -> RuleBuilder<'a> {
+    ///Adds a command to the commands the rule allows.
+    ///Can be called multiple times for rules given a list of commands.
+    pub fn with_cmd(mut self, cmd: &'a str) -> RuleBuilder<'a> {
+        self.cmds.push(cmd);
+        self
+    }
+
+    ///Sets the directories to look commands up in, which should already be checked.
+    pub fn path(self, dirs: Vec<String>) -> RuleBuilder<'a> {
         Self {
-            cmd: Some(cmd),
+            path: Some(dirs),
+            ..self
+        }
+    }
+
+    pub fn digest(self, digest: &'a str) -> RuleBuilder<'a> {
+        Self {
+            digest: Some(digest),
             ..self
         }
     }
@@ -219,8 +815,20 @@ impl<'a> RuleBuilder<'a> {
         }
     }
 
-    //TODO: This probably dosen't need to return a Result anymore.
-    pub fn build(self) -> Result<Rule, ParserError<'static>> {
+    ///Replaces any aliases in the identities, targets and commands with what they stand for.
+    pub fn expand_aliases(self, aliases: &Aliases<'a>) -> Result<Self, ParserError<'a>> {
+        Ok(Self {
+            identity_names: aliases.expand(&self.identity_names)?,
+            targets: aliases.expand(&self.targets)?,
+            cmds: aliases.expand(&self.cmds)?,
+            ..self
+        })
+    }
+
+    ///Builds the rule, compiling its cmd and args patterns.
+    ///Errors if one of the args is an invalid regex, the digest is malformed,
+    ///the cwd or chroot isn't absolute, or the seccomp profile name isn't a plain file name.
+    pub fn build(self) -> Result<Rule, ParserError<'a>> {
         //arguments for doas user.
         let args = ConfigArgs {
             persist: self.persist,
@@ -231,28 +839,126 @@ impl<'a> RuleBuilder<'a> {
                 .into_iter()
                 .map(|(k, v)| (escaped_string(k), escaped_string(v)))
                 .collect(),
-            target: self.target.map(|s| escaped_string(s)),
-            cmd: self.cmd.map(|s| escaped_string(s)),
-            args: self
-                .args
-                .map(|v| v.into_iter().map(|s| escaped_string(s)).collect()),
+            targets: if self.targets.is_empty() {
+                None
+            } else {
+                Some(self.targets.into_iter().map(escaped_string).collect())
+            },
+            cmds: if self.cmds.is_empty() {
+                None
+            } else {
+                Some(self.cmds.into_iter().map(Pattern::new).collect())
+            },
+            path: self.path,
+            allow_dangerous_env: self
+                .allow_dangerous_env
+                .into_iter()
+                .map(escaped_string)
+                .collect(),
+            cwd: match self.cwd {
+                Some("~") => Some(Cwd::Home),
+                Some(dir) => match escaped_string(dir) {
+                    unescaped if unescaped.starts_with('/') => Some(Cwd::Dir(unescaped)),
+                    _ => return Err(ParserError::InvalidCwd(dir)),
+                },
+                None => None,
+            },
+            no_chdir: self.no_chdir,
+            umask: self.umask,
+            rlimits: self.rlimits,
+            nice: self.nice,
+            timeout: self.timeout,
+            caps: self.caps,
+            no_new_privs: self.no_new_privs,
+            sandbox: self.sandbox,
+            use_pty: self.use_pty,
+            record: self.record,
+            chroot: match self.chroot {
+                Some(dir) => match escaped_string(dir) {
+                    unescaped if unescaped.starts_with('/') => Some(unescaped),
+                    _ => return Err(ParserError::InvalidChroot(dir)),
+                },
+                None => None,
+            },
+            seccomp: match self.seccomp {
+                Some(profile) => match escaped_string(profile) {
+                    name if is_profile_name(&name) => Some(name),
+                    _ => return Err(ParserError::InvalidSeccompProfile(profile)),
+                },
+                None => None,
+            },
+            more_args: self.args.as_ref().and_then(|args| args.last()) == Some(&"*"),
+            digest: match self.digest {
+                Some(digest) => {
+                    Some(Digest::parse(digest).ok_or(ParserError::InvalidDigest(digest))?)
+                }
+                None => None,
+            },
+            args: match self.args {
+                Some(mut args) => {
+                    if args.last() == Some(&"*") {
+                        args.pop();
+                    }
+                    Some(compile_args(&args)?)
+                }
+                None => None,
+            },
         };
 
-        let identity = self.identity_name.expect("wasn't given identity name.");
+        assert!(
+            !self.identity_names.is_empty(),
+            "wasn't given identity name."
+        );
 
-        let identity = if let Some(identity) = identity.strip_prefix(':') {
-            UserOrGroup::Group(String::from(identity))
-        } else {
-            UserOrGroup::User(String::from(identity))
-        };
+        let identities = self
+            .identity_names
+            .into_iter()
+            .map(|identity| {
+                let identity = escaped_string(identity);
+                match identity.strip_prefix(':') {
+                    Some(group) => UserOrGroup::Group(group.to_owned()),
+                    None => UserOrGroup::User(identity),
+                }
+            })
+            .collect();
 
         Ok(match self.rule_type.expect("wasn't given rule type") {
-            RuleType::Permit => Rule::Permit(identity, args),
-            RuleType::Deny => Rule::Deny(identity, args),
+            RuleType::Permit => Rule::Permit(identities, args),
+            RuleType::Deny => Rule::Deny(identities, args),
         })
     }
 }
 
+///Compiles the args of a rule into patterns.
+///An unquoted `~` followed by `/regex/` is a regex, anything else is a literal or glob.
+fn compile_args<'a>(args: &[&'a str]) -> Result<Vec<Pattern>, ParserError<'a>> {
+    let mut patterns = vec![];
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        if arg != "~" {
+            patterns.push(Pattern::new(arg));
+            continue;
+        }
+        let regex = *args
+            .next()
+            .ok_or(ParserError::ExpectedRegexGot(super::lexer::Token::Eol))?;
+        let unquoted = regex
+            .strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+            .unwrap_or(regex);
+        let inner = unquoted
+            .strip_prefix('/')
+            .and_then(|r| r.strip_suffix('/'))
+            .ok_or(ParserError::ExpectedRegexGot(super::lexer::Token::Ident(
+                regex,
+            )))?;
+        patterns.push(
+            Pattern::regex(inner).map_err(|e| ParserError::InvalidRegex(regex, e.to_string()))?,
+        );
+    }
+    Ok(patterns)
+}
+
 ///This struct is a helper struct to work with escaped string characters and
 ///to remove and parse them out
 #[derive(Debug, Default)]
@@ -281,7 +987,7 @@ impl EscapeString {
 }
 
 ///Returns a string with parsed out escape characters.
-fn escaped_string(s: &str) -> String {
+pub(super) fn escaped_string(s: &str) -> String {
     let mut escaped_string_state = EscapeString::new();
     s.chars()
         .filter(|&c| escaped_string_state.should_char_be_escaped(c))
@@ -0,0 +1,138 @@
+//! This module defines the global settings a config can have, such as `path { /usr/bin /bin }`.
+//! A setting applies to every rule which doesn't give its own value for it, wherever in the
+//! config it's written. If a setting is given more than once, the last one wins.
+use super::lexer::Token;
+use super::pattern::Pattern;
+use super::rules::escaped_string;
+use super::ParserError;
+
+///The variables doas sets to describe who ran it and what with, for the command to read.
+///Which of these get set can be chosen with `export`.
+pub const CALLER_VARS: &[&str] = &[
+    "DOAS_USER",
+    "DOAS_UID",
+    "DOAS_GID",
+    "DOAS_COMMAND",
+    "DOAS_TTY",
+    "SUDO_USER",
+    "SUDO_UID",
+    "SUDO_GID",
+    "SUDO_COMMAND",
+];
+
+///A global setting, as written in the config.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Setting {
+    ///The directories commands are looked up in, and the PATH they're run with.
+    Path(Vec<String>),
+    ///Which of the CALLER_VARS to set, such as `export { DOAS_* SUDO_USER }`.
+    ///All of them are set if this isn't given.
+    Export(Vec<Pattern>),
+    ///The umask commands run with.
+    Umask(Umask),
+    ///Run every command on a pty of its own, given with `usepty`.
+    UsePty,
+    ///The file to write a JSON line to for every authorization decision, and every command exit.
+    AuditLog(String),
+    ///Where authorization decisions are logged.
+    Logger(Logger),
+}
+
+///The umask to run a command with, given with `umask 027` or `umask preserve`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Umask {
+    ///Keep the caller's umask.
+    Preserve,
+    Mask(u32),
+}
+
+impl std::fmt::Display for Umask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Preserve => write!(f, "preserve"),
+            Self::Mask(mask) => write!(f, "{:03o}", mask),
+        }
+    }
+}
+
+///Where doas logs authorization decisions, given with `logger syslog` or `logger journald`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Logger {
+    #[default]
+    Syslog,
+    ///The systemd journal, with each detail in a field of its own.
+    Journald,
+}
+
+///Makes the setting started by token out of the values given for it.
+pub(super) fn setting<'a>(
+    token: &Token<'a>,
+    values: Vec<&'a str>,
+) -> Result<Setting, ParserError<'a>> {
+    match token {
+        Token::Path => search_path(values).map(Setting::Path),
+        Token::Export => export_vars(values).map(Setting::Export),
+        Token::Umask => match values[..] {
+            [value] => umask(value).map(Setting::Umask),
+            _ => Err(ParserError::ExpectedUmaskGot(Token::OpenBrace)),
+        },
+        Token::AuditLog => match values[..] {
+            [file] => match escaped_string(file) {
+                unescaped if unescaped.starts_with('/') => Ok(Setting::AuditLog(unescaped)),
+                _ => Err(ParserError::InvalidAuditLog(file)),
+            },
+            _ => Err(ParserError::ExpectedAuditLogGot(Token::OpenBrace)),
+        },
+        Token::Logger => match values[..] {
+            ["syslog"] => Ok(Setting::Logger(Logger::Syslog)),
+            ["journald"] => Ok(Setting::Logger(Logger::Journald)),
+            [logger] => Err(ParserError::UnknownLogger(logger)),
+            _ => Err(ParserError::ExpectedLoggerGot(Token::OpenBrace)),
+        },
+        _ => unreachable!("{} doesn't start a setting", token),
+    }
+}
+
+///Checks the directories of a `path` setting or option are all absolute.
+pub(super) fn search_path(dirs: Vec<&str>) -> Result<Vec<String>, ParserError<'_>> {
+    dirs.into_iter()
+        .map(|dir| {
+            let unescaped = escaped_string(dir);
+            if unescaped.starts_with('/') && !unescaped.contains(':') {
+                Ok(unescaped)
+            } else {
+                Err(ParserError::InvalidPathDir(dir))
+            }
+        })
+        .collect()
+}
+
+///Parses the value of a `umask` setting or option, in octal.
+pub(super) fn umask(value: &str) -> Result<Umask, ParserError<'_>> {
+    if value == "preserve" {
+        return Ok(Umask::Preserve);
+    }
+    //from_str_radix would also take a leading '+'.
+    if value.is_empty() || value.len() > 4 || !value.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return Err(ParserError::InvalidUmask(value));
+    }
+    match u32::from_str_radix(value, 8) {
+        Ok(mask) if mask <= 0o777 => Ok(Umask::Mask(mask)),
+        _ => Err(ParserError::InvalidUmask(value)),
+    }
+}
+
+///Compiles the patterns of an `export` setting, each of which has to match one of CALLER_VARS,
+///so a typo doesn't silently stop a variable from being set.
+fn export_vars(vars: Vec<&str>) -> Result<Vec<Pattern>, ParserError<'_>> {
+    vars.into_iter()
+        .map(|var| {
+            let pattern = Pattern::new(var);
+            if CALLER_VARS.iter().any(|known| pattern.matches(known)) {
+                Ok(pattern)
+            } else {
+                Err(ParserError::UnknownExportVar(var))
+            }
+        })
+        .collect()
+}
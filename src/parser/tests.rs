@@ -1,5 +1,6 @@
 use super::*;
 use std::collections::HashMap;
+use std::path::Path;
 
 #[test]
 fn check_parse_line() {
@@ -151,3 +152,953 @@ fn check_parse_error_then_line() {
         ]
     )
 }
+
+#[test]
+fn check_parse_identity_and_target_lists() {
+    assert_eq!(
+        parse_rules("permit { alice bob :ops } as { www postgres } cmd systemctl"),
+        vec![rules::RuleBuilder::new()
+            .permit()
+            .identity_name("alice")
+            .identity_name("bob")
+            .identity_name(":ops")
+            .target("www")
+            .target("postgres")
+            .with_cmd("systemctl")
+            .build()]
+    )
+}
+
+#[test]
+fn check_parse_list_without_spaces() {
+    assert_eq!(
+        parse_rules("permit nopass {alice :ops}as{www}"),
+        vec![rules::RuleBuilder::new()
+            .permit()
+            .no_pass()
+            .identity_name("alice")
+            .identity_name(":ops")
+            .target("www")
+            .build()]
+    )
+}
+
+#[test]
+fn check_braces_in_args() {
+    let rule = parse_rules("permit alice as root cmd find args / -name {a,b} -exec rm {} ;")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed(
+            "alice",
+            vec![],
+            "find",
+            None,
+            &["/", "-name", "{a,b}", "-exec", "rm", "{}", ";"],
+            "root"
+        ),
+        Some(true)
+    );
+}
+
+#[test]
+fn check_empty_list() {
+    assert_eq!(
+        parse_rules("permit { } as root\n permit bender"),
+        vec![
+            Err(ParserError::EmptyList),
+            rules::RuleBuilder::new()
+                .permit()
+                .identity_name("bender")
+                .build()
+        ]
+    )
+}
+
+#[test]
+fn check_unclosed_list() {
+    assert_eq!(
+        parse_rules("permit alice as { www postgres\n permit bender"),
+        vec![
+            Err(ParserError::ExpectedListItemGot(lexer::Token::Eol)),
+            rules::RuleBuilder::new()
+                .permit()
+                .identity_name("bender")
+                .build()
+        ]
+    )
+}
+
+#[test]
+fn check_list_matching() {
+    let rule = parse_rules("permit { alice :ops } as { www postgres } cmd systemctl")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed("alice", vec![], "systemctl", None, &[], "www"),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed("carol", vec!["ops"], "systemctl", None, &[], "postgres"),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed("carol", vec!["users"], "systemctl", None, &[], "www"),
+        None
+    );
+    assert_eq!(
+        rule.is_allowed("alice", vec![], "systemctl", None, &[], "root"),
+        None
+    );
+}
+
+#[test]
+fn check_parse_aliases() {
+    assert_eq!(
+        parse_rules(
+            "alias ADMINS = { alice :wheel }\n alias PKG = { /usr/bin/apt /usr/bin/dpkg }\n permit ADMINS as root cmd PKG"
+        ),
+        vec![rules::RuleBuilder::new()
+            .permit()
+            .identity_name("alice")
+            .identity_name(":wheel")
+            .target("root")
+            .with_cmd("/usr/bin/apt")
+            .with_cmd("/usr/bin/dpkg")
+            .build()]
+    )
+}
+
+#[test]
+fn check_nested_alias_used_before_definition() {
+    assert_eq!(
+        parse_rules(
+            "permit { STAFF carol } as root\n alias STAFF = { ADMINS bob }\n alias ADMINS = alice"
+        ),
+        vec![rules::RuleBuilder::new()
+            .permit()
+            .identity_name("alice")
+            .identity_name("bob")
+            .identity_name("carol")
+            .target("root")
+            .build()]
+    )
+}
+
+#[test]
+fn check_undefined_alias() {
+    assert_eq!(
+        parse_rules("permit ADMINS as root\n deny bender cmd PKG\n permit bender"),
+        vec![
+            Err(ParserError::UndefinedAlias("ADMINS")),
+            Err(ParserError::UndefinedAlias("PKG")),
+            rules::RuleBuilder::new()
+                .permit()
+                .identity_name("bender")
+                .build()
+        ]
+    )
+}
+
+#[test]
+fn check_quoted_upper_case_names() {
+    let rules = parse_rules(r#"permit { "BOB" ":WHEEL" } as "SVC" cmd "LS""#);
+    let rule = rules[0].as_ref().unwrap();
+    assert_eq!(
+        rule.is_allowed("BOB", vec![], "LS", None, &[], "SVC"),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed("alice", vec!["WHEEL"], "LS", None, &[], "SVC"),
+        Some(true)
+    );
+}
+
+#[test]
+fn check_recursive_alias() {
+    assert_eq!(
+        parse_rules("alias A = { B alice }\n alias B = { A }\n permit A"),
+        vec![Err(ParserError::RecursiveAlias("A"))]
+    )
+}
+
+#[test]
+fn check_bad_alias_definitions() {
+    assert_eq!(
+        parse_rules(
+            "alias admins = alice\n alias ADMINS alice\n alias ADMINS = bob\n alias ADMINS = carol"
+        ),
+        vec![
+            Err(ParserError::InvalidAliasName("admins")),
+            Err(ParserError::ExpectedEqualsGot(lexer::Token::from("alice"))),
+            Err(ParserError::DuplicateAlias("ADMINS")),
+        ]
+    )
+}
+
+#[test]
+fn check_display_expanded_rule() {
+    let rule = parse_rules(
+        "alias PKG = { /usr/bin/apt /usr/bin/dpkg }\n permit nopass { alice :wheel } as root cmd PKG args install \"a b\"",
+    )
+    .pop()
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        rule.to_string(),
+        "permit nopass { alice :wheel } as root cmd { /usr/bin/apt /usr/bin/dpkg } args install \"a b\""
+    );
+}
+
+#[test]
+fn check_parse_config_includes_and_lines() {
+    assert_eq!(
+        parse_config("# ops rules\ninclude \"ops.conf\"\n\npermit bender\ninclude\n"),
+        vec![
+            (2, Ok(Entry::Include(String::from("ops.conf")))),
+            (
+                4,
+                Ok(Entry::Rule(
+                    rules::RuleBuilder::new()
+                        .permit()
+                        .identity_name("bender")
+                        .build()
+                        .unwrap()
+                ))
+            ),
+            (
+                5,
+                Err(ParserError::ExpectedIncludePathGot(lexer::Token::Eol))
+            ),
+        ]
+    )
+}
+
+#[test]
+fn check_glob_matching() {
+    let rule = parse_rules("permit ops cmd systemctl args restart nginx@*")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "systemctl",
+            None,
+            &["restart", "nginx@web"],
+            "root"
+        ),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "systemctl",
+            None,
+            &["restart", "sshd"],
+            "root"
+        ),
+        None
+    );
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "systemctl",
+            None,
+            &["restart", "nginx@a", "b"],
+            "root"
+        ),
+        None
+    );
+
+    let rule = parse_rules("permit ops cmd /usr/local/bin/deploy-*")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "/usr/local/bin/deploy-web",
+            Some(Path::new("/usr/local/bin/deploy-web")),
+            &["-v"],
+            "root"
+        ),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "/usr/local/bin/deploy-/../../../bin/sh",
+            Some(Path::new("/usr/local/bin/deploy-/../../../bin/sh")),
+            &[],
+            "root"
+        ),
+        None
+    );
+}
+
+#[test]
+fn check_trailing_star_matches_remaining_args() {
+    let rule = parse_rules("permit ops cmd apt args install *")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "apt", None, &["install"], "root"),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "apt",
+            None,
+            &["install", "vim", "git"],
+            "root"
+        ),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "apt", None, &["remove", "vim"], "root"),
+        None
+    );
+    assert_eq!(rule.to_string(), "permit ops cmd apt args install *");
+}
+
+#[test]
+fn check_quoted_wildcards_are_literal() {
+    let rule = parse_rules("permit ops cmd echo args \"nginx@*\" \"*\"")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "echo", None, &["nginx@*", "*"], "root"),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "echo", None, &["nginx@web", "*"], "root"),
+        None
+    );
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "echo", None, &["nginx@*", "a"], "root"),
+        None
+    );
+    assert_eq!(
+        rule.to_string(),
+        "permit ops cmd echo args \"nginx@*\" \"*\""
+    );
+}
+
+#[test]
+fn check_regex_args() {
+    let rule = parse_rules(r"permit ops cmd journalctl args -u ~ /[a-z0-9-]+\.service/")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "journalctl",
+            None,
+            &["-u", "nginx.service"],
+            "root"
+        ),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "journalctl",
+            None,
+            &["-u", "nginx.socket"],
+            "root"
+        ),
+        None
+    );
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "journalctl",
+            None,
+            &["-u", "a.service -f"],
+            "root"
+        ),
+        None
+    );
+    for arg in &["nginx.service\n", " nginx.service"] {
+        assert_eq!(
+            rule.is_allowed("ops", vec![], "journalctl", None, &["-u", arg], "root"),
+            None
+        );
+    }
+    assert_eq!(
+        rule.to_string(),
+        r"permit ops cmd journalctl args -u ~ /[a-z0-9-]+\.service/"
+    );
+}
+
+#[test]
+fn check_invalid_regex_args() {
+    let rules =
+        parse_rules("permit ops cmd journalctl args -u ~ /[a-z/\n permit ops cmd ls args ~ a");
+    assert!(matches!(
+        rules[0],
+        Err(ParserError::InvalidRegex("/[a-z/", _))
+    ));
+    assert_eq!(
+        rules[1],
+        Err(ParserError::ExpectedRegexGot(lexer::Token::Ident("a")))
+    );
+    assert_eq!(
+        parse_rules("permit ops cmd ls args \"~\" ~/notes"),
+        vec![rules::RuleBuilder::new()
+            .permit()
+            .identity_name("ops")
+            .with_cmd("ls")
+            .with_cmd_args(vec!["\"~\"", "~/notes"])
+            .build()]
+    );
+}
+
+#[test]
+fn check_bare_cmd_only_matches_bare_names() {
+    let rule = parse_rules("permit ops cmd reboot").pop().unwrap().unwrap();
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "reboot",
+            Some(Path::new("/usr/sbin/reboot")),
+            &[],
+            "root"
+        ),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "./reboot",
+            Some(Path::new("/home/ops/reboot")),
+            &[],
+            "root"
+        ),
+        None
+    );
+}
+
+#[test]
+fn check_absolute_cmd_matches_resolved_path() {
+    let rule = parse_rules("permit ops cmd /usr/sbin/reboot")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "reboot",
+            Some(Path::new("/usr/sbin/reboot")),
+            &[],
+            "root"
+        ),
+        Some(true)
+    );
+    assert_eq!(
+        rule.is_allowed(
+            "ops",
+            vec![],
+            "/usr/sbin/reboot",
+            Some(Path::new("/tmp/reboot")),
+            &[],
+            "root"
+        ),
+        None
+    );
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "/usr/sbin/reboot", None, &[], "root"),
+        None
+    );
+
+    //The rule's own path is canonicalized too, so it can go through symlinks.
+    let rule = parse_rules("permit ops cmd /bin/sh")
+        .pop()
+        .unwrap()
+        .unwrap();
+    let sh = Path::new("/bin/sh").canonicalize().unwrap();
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "sh", Some(&sh), &[], "root"),
+        Some(true)
+    );
+}
+
+#[test]
+fn check_parse_digest() {
+    let digest = "sha256:ABCDEF0123456789abcdef0123456789abcdef0123456789abcdef0123456789";
+    let rule = parse_rules(&format!(
+        "permit ops as root cmd /opt/tool/deploy digest {} args --prod",
+        digest
+    ))
+    .pop()
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        rule.get_digest().map(|d| d.as_hex()),
+        Some("abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789")
+    );
+    assert_eq!(
+        rule.to_string(),
+        format!(
+            "permit ops as root cmd /opt/tool/deploy digest {} args --prod",
+            digest.to_ascii_lowercase()
+        )
+    );
+}
+
+#[test]
+fn check_invalid_digest() {
+    assert_eq!(
+        parse_rules("permit ops cmd deploy digest md5:abcd\n permit ops cmd deploy digest"),
+        vec![
+            Err(ParserError::InvalidDigest("md5:abcd")),
+            Err(ParserError::ExpectedDigestGot(lexer::Token::Eol)),
+        ]
+    );
+}
+
+#[test]
+fn check_parse_path() {
+    let entries = parse_config(
+        "path { /opt/bin /usr/bin }\npermit nopass path /srv/bin ops cmd deploy\npath /bin",
+    );
+    let entries: Vec<_> = entries
+        .into_iter()
+        .map(|(line, e)| (line, e.unwrap()))
+        .collect();
+    assert_eq!(
+        entries[0],
+        (
+            1,
+            Entry::Setting(Setting::Path(vec![
+                "/opt/bin".to_owned(),
+                "/usr/bin".to_owned()
+            ]))
+        )
+    );
+    assert_eq!(
+        entries[2],
+        (3, Entry::Setting(Setting::Path(vec!["/bin".to_owned()])))
+    );
+    match &entries[1] {
+        (2, Entry::Rule(rule)) => {
+            assert_eq!(rule.get_path(), Some(&["/srv/bin".to_owned()][..]));
+            assert_eq!(
+                rule.to_string(),
+                "permit nopass path { /srv/bin } ops cmd deploy"
+            );
+        }
+        other => panic!("expected a rule, got {:?}", other),
+    }
+}
+
+#[test]
+fn check_invalid_path() {
+    //Errors in a path skip the rest of the line, so the rule after it still parses.
+    let entries = parse_config("path { /bin bin }\npermit path . ops cmd ls\npermit ops cmd ls");
+    let entries: Vec<_> = entries
+        .into_iter()
+        .map(|(line, e)| (line, e.is_ok()))
+        .collect();
+    assert_eq!(entries, vec![(1, false), (2, false), (3, true)]);
+    assert_eq!(
+        parse_rules("permit path { /usr/bin:/bin } ops"),
+        vec![Err(ParserError::InvalidPathDir("/usr/bin:/bin"))]
+    );
+}
+
+#[test]
+fn check_parse_export() {
+    let entries = parse_config("export { DOAS_* SUDO_COMMAND }\nexport SUDO_HOME");
+    assert_eq!(
+        entries[0],
+        (
+            1,
+            Ok(Entry::Setting(Setting::Export(vec![
+                pattern::Pattern::new("DOAS_*"),
+                pattern::Pattern::new("SUDO_COMMAND")
+            ])))
+        )
+    );
+    assert_eq!(
+        entries[1],
+        (2, Err(ParserError::UnknownExportVar("SUDO_HOME")))
+    );
+}
+
+#[test]
+fn check_parse_allow_dangerous_env() {
+    let rule = parse_rules(
+        "permit setenv { LD_LIBRARY_PATH=/opt/lib } allow_dangerous_env LD_LIBRARY_PATH ops",
+    )
+    .pop()
+    .unwrap()
+    .unwrap();
+    assert_eq!(rule.get_allow_dangerous_env(), ["LD_LIBRARY_PATH"]);
+    assert_eq!(
+        rule.to_string(),
+        "permit setenv { LD_LIBRARY_PATH=/opt/lib } allow_dangerous_env LD_LIBRARY_PATH ops"
+    );
+}
+
+#[test]
+fn check_parse_cwd() {
+    let rules = parse_rules(
+        "permit cwd /srv/app nochdir ops\npermit cwd ~ ops\npermit cwd srv ops\npermit cwd",
+    );
+    assert_eq!(
+        rules[0].as_ref().unwrap().get_cwd(),
+        Some(&rules::Cwd::Dir("/srv/app".to_owned()))
+    );
+    assert!(rules[0].as_ref().unwrap().get_no_chdir());
+    assert_eq!(rules[1].as_ref().unwrap().to_string(), "permit cwd ~ ops");
+    assert_eq!(rules[2], Err(ParserError::InvalidCwd("srv")));
+    assert_eq!(
+        rules[3],
+        Err(ParserError::ExpectedCwdGot(lexer::Token::Eol))
+    );
+}
+
+#[test]
+fn check_parse_umask() {
+    use settings::Umask;
+    let entries = parse_config(
+        "umask 077\npermit umask 027 ops\npermit umask preserve ops\npermit umask 0800 ops",
+    );
+    assert_eq!(
+        entries[0],
+        (1, Ok(Entry::Setting(Setting::Umask(Umask::Mask(0o77)))))
+    );
+    match &entries[1] {
+        (2, Ok(Entry::Rule(rule))) => {
+            assert_eq!(rule.get_umask(), Some(Umask::Mask(0o27)));
+            assert_eq!(rule.to_string(), "permit umask 027 ops");
+        }
+        other => panic!("expected a rule, got {:?}", other),
+    }
+    match &entries[2] {
+        (3, Ok(Entry::Rule(rule))) => assert_eq!(rule.get_umask(), Some(Umask::Preserve)),
+        other => panic!("expected a rule, got {:?}", other),
+    }
+    assert_eq!(entries[3], (4, Err(ParserError::InvalidUmask("0800"))));
+    assert_eq!(
+        parse_rules("permit umask +77 ops"),
+        vec![Err(ParserError::InvalidUmask("+77"))]
+    );
+    assert_eq!(
+        parse_config("umask { 1 2 }"),
+        vec![(
+            1,
+            Err(ParserError::ExpectedUmaskGot(lexer::Token::OpenBrace))
+        )]
+    );
+}
+
+#[test]
+fn check_parse_rlimit_and_nice() {
+    use limits::{Resource, Rlimit};
+    let rule = parse_rules("permit rlimit { nofile 4096 as 2G cpu infinity } nice 10 ops")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.get_rlimits(),
+        [
+            Rlimit {
+                resource: Resource::Nofile,
+                limit: Some(4096)
+            },
+            Rlimit {
+                resource: Resource::As,
+                limit: Some(2 << 30)
+            },
+            Rlimit {
+                resource: Resource::Cpu,
+                limit: None
+            },
+        ]
+    );
+    assert_eq!(rule.get_nice(), Some(10));
+    assert_eq!(
+        rule.to_string(),
+        "permit rlimit { nofile 4096 as 2147483648 cpu infinity } nice 10 ops"
+    );
+
+    assert_eq!(
+        parse_rules(
+            "permit rlimit files 10 ops\npermit rlimit nofile lots ops\npermit nice 20 ops"
+        ),
+        vec![
+            Err(ParserError::UnknownRlimit("files")),
+            Err(ParserError::InvalidRlimit("lots")),
+            Err(ParserError::InvalidNice("20")),
+        ]
+    );
+}
+
+#[test]
+fn check_parse_timeout() {
+    let rules = parse_rules(
+        "permit timeout 15m ops\npermit timeout 90 ops\npermit timeout 0 ops\npermit timeout 5y ops",
+    );
+    let rule = rules[0].as_ref().unwrap();
+    assert_eq!(
+        rule.get_timeout(),
+        Some(std::time::Duration::from_secs(900))
+    );
+    assert_eq!(rule.to_string(), "permit timeout 15m ops");
+    assert_eq!(
+        rules[1].as_ref().unwrap().to_string(),
+        "permit timeout 90s ops"
+    );
+    assert_eq!(rules[2], Err(ParserError::InvalidTimeout("0")));
+    assert_eq!(rules[3], Err(ParserError::InvalidTimeout("5y")));
+}
+
+#[test]
+fn check_keywords_as_cmd_and_args() {
+    assert_eq!(
+        parse_rules(
+            "permit ops cmd timeout
+ permit ops cmd { nice chroot } args nice 5 path"
+        ),
+        vec![
+            rules::RuleBuilder::new()
+                .permit()
+                .identity_name("ops")
+                .with_cmd("timeout")
+                .build(),
+            rules::RuleBuilder::new()
+                .permit()
+                .identity_name("ops")
+                .with_cmd("nice")
+                .with_cmd("chroot")
+                .with_cmd_args(vec!["nice", "5", "path"])
+                .build()
+        ]
+    );
+    let rule = parse_rules("permit ops cmd ls args timeout -l")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "ls", None, &["-l"], "root"),
+        None
+    );
+    assert_eq!(
+        rule.is_allowed("ops", vec![], "ls", None, &["timeout", "-l"], "root"),
+        Some(true)
+    );
+}
+
+#[test]
+fn check_option_after_cmd() {
+    assert_eq!(
+        parse_rules(
+            "permit ops cmd ls nopass
+ permit ops cmd ls args setenv { A 1 }
+ permit ops"
+        ),
+        vec![
+            Err(ParserError::ExpectedArgsGot(lexer::Token::NoPass)),
+            Err(ParserError::ExpectedArgGot(lexer::Token::SetEnv(
+                [("A", "1")].iter().cloned().collect()
+            ))),
+            rules::RuleBuilder::new()
+                .permit()
+                .identity_name("ops")
+                .build()
+        ]
+    );
+}
+
+#[test]
+fn check_parse_caps() {
+    let rules =
+        parse_rules("permit caps { CAP_NET_RAW net_bind_service } ops\npermit caps cap_fly ops");
+    let rule = rules[0].as_ref().unwrap();
+    assert_eq!(
+        rule.get_caps()
+            .iter()
+            .map(|c| c.number())
+            .collect::<Vec<_>>(),
+        [13, 10]
+    );
+    assert_eq!(
+        rule.to_string(),
+        "permit caps { cap_net_raw cap_net_bind_service } ops"
+    );
+    assert_eq!(rules[1], Err(ParserError::UnknownCapability("cap_fly")));
+}
+
+#[test]
+fn check_parse_nonewprivs_and_seccomp() {
+    let rules = parse_rules(
+        "permit nonewprivs seccomp \"no-ptrace\" ops\npermit seccomp ../../tmp/x ops\npermit seccomp",
+    );
+    let rule = rules[0].as_ref().unwrap();
+    assert!(rule.get_no_new_privs());
+    assert_eq!(rule.get_seccomp(), Some("no-ptrace"));
+    assert_eq!(
+        rule.to_string(),
+        "permit nonewprivs seccomp \"no-ptrace\" ops"
+    );
+    assert_eq!(
+        rules[1],
+        Err(ParserError::InvalidSeccompProfile("../../tmp/x"))
+    );
+    assert_eq!(
+        rules[2],
+        Err(ParserError::ExpectedSeccompGot(lexer::Token::Eol))
+    );
+}
+
+#[test]
+fn check_parse_sandbox() {
+    let rule = parse_rules("permit private-tmp no-network ops cmd tcpdump")
+        .pop()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        rule.get_sandbox(),
+        rules::Sandbox {
+            private_tmp: true,
+            readonly_root: false,
+            no_network: true,
+        }
+    );
+    assert_eq!(
+        rule.to_string(),
+        "permit private-tmp no-network ops cmd tcpdump"
+    );
+}
+
+#[test]
+fn check_parse_chroot() {
+    let rules =
+        parse_rules("permit chroot /srv/jail ops\npermit chroot srv/jail ops\npermit chroot");
+    assert_eq!(
+        rules[0].as_ref().unwrap().get_chroot(),
+        Some(std::path::Path::new("/srv/jail"))
+    );
+    assert_eq!(
+        rules[0].as_ref().unwrap().to_string(),
+        "permit chroot /srv/jail ops"
+    );
+    assert_eq!(rules[1], Err(ParserError::InvalidChroot("srv/jail")));
+    assert_eq!(
+        rules[2],
+        Err(ParserError::ExpectedChrootGot(lexer::Token::Eol))
+    );
+}
+
+#[test]
+fn check_cmd_symlinks_in_chroot() {
+    let jail = std::env::temp_dir().join(format!("doas-chroot-cmd-test-{}", std::process::id()));
+    std::fs::create_dir_all(jail.join("usr/bin")).unwrap();
+    std::fs::create_dir_all(jail.join("bin")).unwrap();
+    std::fs::write(jail.join("usr/bin/tool"), "").unwrap();
+    std::os::unix::fs::symlink("/usr/bin", jail.join("sbin")).unwrap();
+    let config = format!(
+        "permit chroot {0} ops cmd /sbin/tool\npermit chroot {0} ops cmd /bin/env",
+        jail.display()
+    );
+    let rules = parse_rules(&config);
+    let allowed = |rule: &Result<Rule, ParserError<'_>>, resolved| {
+        rule.as_ref().unwrap().is_allowed(
+            "ops",
+            vec![],
+            "x",
+            Some(Path::new(resolved)),
+            &[],
+            "root",
+        )
+    };
+    //The jail's /sbin leads to its /usr/bin, which the host's doesn't.
+    let sbin_tool = allowed(&rules[0], "/usr/bin/tool");
+    //The jail's /bin is a directory of its own, unlike the host's, which leads to /usr/bin.
+    let bin_env = allowed(&rules[1], "/usr/bin/env");
+    std::fs::remove_dir_all(&jail).unwrap();
+    assert_eq!(sbin_tool, Some(true));
+    assert_eq!(bin_env, None);
+}
+
+#[test]
+fn check_parse_usepty() {
+    let entries = parse_config("usepty\npermit usepty ops\nusepty ops");
+    assert_eq!(entries[0], (1, Ok(Entry::Setting(Setting::UsePty))));
+    match &entries[1] {
+        (2, Ok(Entry::Rule(rule))) => {
+            assert!(rule.get_use_pty());
+            assert_eq!(rule.to_string(), "permit usepty ops");
+        }
+        other => panic!("expected a rule, got {:?}", other),
+    }
+    assert_eq!(
+        entries[2],
+        (
+            3,
+            Err(ParserError::ExpectedEolGot(lexer::Token::Ident("ops")))
+        )
+    );
+}
+
+#[test]
+fn check_parse_record() {
+    let rules = parse_rules("permit record ops\npermit ops");
+    assert!(rules[0].as_ref().unwrap().get_record());
+    assert_eq!(rules[0].as_ref().unwrap().to_string(), "permit record ops");
+    assert!(!rules[1].as_ref().unwrap().get_record());
+}
+
+#[test]
+fn check_parse_auditlog() {
+    let entries = parse_config("auditlog /var/log/doas/audit.json\nauditlog audit.json");
+    assert_eq!(
+        entries[0],
+        (
+            1,
+            Ok(Entry::Setting(Setting::AuditLog(
+                "/var/log/doas/audit.json".to_owned()
+            )))
+        )
+    );
+    assert_eq!(
+        entries[1],
+        (2, Err(ParserError::InvalidAuditLog("audit.json")))
+    );
+}
+
+#[test]
+fn check_parse_logger() {
+    use settings::Logger;
+    let entries = parse_config("logger journald\nlogger syslog\nlogger stderr");
+    assert_eq!(
+        entries[0],
+        (1, Ok(Entry::Setting(Setting::Logger(Logger::Journald))))
+    );
+    assert_eq!(
+        entries[1],
+        (2, Ok(Entry::Setting(Setting::Logger(Logger::Syslog))))
+    );
+    assert_eq!(entries[2], (3, Err(ParserError::UnknownLogger("stderr"))));
+}